@@ -1,6 +1,6 @@
 use std::collections::HashMap;
-use std::process::Command;
-use std::io;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::io::{self, Read};
 
 #[derive(Debug)]
 pub struct PhpExecContext {
@@ -40,4 +40,44 @@ impl PhpExecContext {
 
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
+
+    /// Starts the script and returns its output as it is produced.
+    pub fn spawn(&self) -> io::Result<CgiOutput> {
+        let mut child = Command::new(&self.bin_path)
+            .env_clear() // Clear existing environment
+            .envs(&self.envs)
+            .arg(&self.script_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::other("Script stdout not captured"))?;
+
+        Ok(CgiOutput { child, stdout })
+    }
+}
+
+/// Stdout of a running script. The script is killed and reaped once this is
+/// dropped, so a client going away does not leave it running.
+#[derive(Debug)]
+pub struct CgiOutput {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for CgiOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for CgiOutput {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
 }
\ No newline at end of file
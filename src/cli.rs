@@ -0,0 +1,157 @@
+use kang::config::{Overrides, ServeDir};
+use kang::logging::LogLevel;
+
+pub const DEFAULT_CONFIG: &str = "config/kangrc";
+
+pub const USAGE: &str = "\
+Usage: kang [COMMAND] [OPTIONS] [CONFIG]
+       kang serve-dir [DIR] [OPTIONS]
+
+Commands:
+  serve         Start the server (default)
+  serve-dir     Serve DIR (default .) without a config file, on port 8000
+  dump-config   Print the configuration in effect, after defaults and overrides
+  reload        Tell the running kang to reload its configuration
+  stop          Tell the running kang to shut down and wait for it to exit
+
+Options:
+  -c, --config <FILE>       Config file [default: config/kangrc]
+  -t, --test-config         Check the configuration, then exit
+  -p, --port <PORT>         Listen on PORT instead of the configured ports
+  -l, --log-level <LEVEL>   Log level: debug, info, warn or error
+      --no-banner           Do not print the banner on startup
+  -h, --help                Print this help
+  -V, --version             Print the version
+
+serve-dir options:
+      --host <HOST>         Address to listen on [default: 127.0.0.1]
+      --listing             List directories without an index.html
+      --spa                 Answer paths matching no file with index.html
+      --cors                Allow cross-origin requests from any origin
+      --upload              Save files POSTed as multipart form data to DIR
+
+reload and stop find the running kang through global.pid of the config.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    TestConfig,
+    DumpConfig,
+    ServeDir,
+    Reload,
+    Stop,
+    Help,
+    Version,
+}
+
+/// The parsed command line.
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub config_path: String,
+    pub overrides: Overrides,
+    pub banner: bool,
+    /// What `serve-dir` serves, and how
+    pub serve_dir: ServeDir,
+}
+
+impl Cli {
+    /// Parses the arguments following the program name. The config path is
+    /// given with `--config` or as the only positional argument, which is the
+    /// directory instead for `serve-dir`.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+        let mut command = None;
+        let mut config_path = None;
+        let mut overrides = Overrides::default();
+        let mut banner = true;
+        let mut serve_dir = ServeDir::default();
+        let mut root = None;
+        // serve-dir options given, which no other command accepts
+        let mut serve_dir_option = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Accept both `--port 8000` and `--port=8000`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+
+            match flag.as_str() {
+                "-c" | "--config" => config_path = Some(value(&flag)?),
+                "-p" | "--port" => {
+                    let port = value(&flag)?;
+                    match port.parse::<u16>() {
+                        Ok(port) if port > 0 => overrides.port = Some(port),
+                        _ => return Err(format!("Invalid port '{}'", port)),
+                    }
+                }
+                "-l" | "--log-level" => overrides.log_level = Some(value(&flag)?.parse::<LogLevel>()?),
+                "--no-banner" => banner = false,
+                "--host" => {
+                    serve_dir.host = value(&flag)?;
+                    serve_dir_option = Some(flag);
+                }
+                "--listing" | "--spa" | "--cors" | "--upload" => {
+                    match flag.as_str() {
+                        "--listing" => serve_dir.listing = true,
+                        "--spa" => serve_dir.spa = true,
+                        "--cors" => serve_dir.cors = true,
+                        _ => serve_dir.upload = true,
+                    }
+                    serve_dir_option = Some(flag);
+                }
+                "-t" | "--test-config" => set_command(&mut command, Command::TestConfig)?,
+                "-h" | "--help" => set_command(&mut command, Command::Help)?,
+                "-V" | "--version" => set_command(&mut command, Command::Version)?,
+                _ if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("Unknown option '{}'", flag));
+                }
+                "serve" if config_path.is_none() => set_command(&mut command, Command::Serve)?,
+                "dump-config" if config_path.is_none() => set_command(&mut command, Command::DumpConfig)?,
+                "serve-dir" if config_path.is_none() => set_command(&mut command, Command::ServeDir)?,
+                "reload" if config_path.is_none() => set_command(&mut command, Command::Reload)?,
+                "stop" if config_path.is_none() => set_command(&mut command, Command::Stop)?,
+                _ if command == Some(Command::ServeDir) && root.is_none() => root = Some(arg),
+                _ if command != Some(Command::ServeDir) && config_path.is_none() => config_path = Some(arg),
+                _ => return Err(format!("Unexpected argument '{}'", arg)),
+            }
+        }
+
+        let command = command.unwrap_or(Command::Serve);
+        if command == Command::ServeDir {
+            if let Some(root) = root {
+                serve_dir.root = root;
+            }
+            if let Some(port) = overrides.port.take() {
+                serve_dir.port = port;
+            }
+        } else if let Some(option) = serve_dir_option {
+            return Err(format!("{} is only accepted by serve-dir", option));
+        }
+
+        Ok(Cli {
+            command,
+            config_path: config_path.unwrap_or_else(|| DEFAULT_CONFIG.to_string()),
+            overrides,
+            banner,
+            serve_dir,
+        })
+    }
+}
+
+fn set_command(command: &mut Option<Command>, new: Command) -> Result<(), String> {
+    match command {
+        Some(current) if *current != new => Err("Only one command can be given".to_string()),
+        _ => {
+            *command = Some(new);
+            Ok(())
+        }
+    }
+}
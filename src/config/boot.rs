@@ -1,23 +1,125 @@
-use std::{net::TcpListener, thread};
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    process::Child,
+    sync::mpsc::Sender,
+    thread,
+    time::{Duration, Instant},
+};
 
-use super::config::Config;
-use crate::{error, warn};
-#[cfg(target_os = "macos")]
-use crate::server::listener::KqueueListener;
-#[cfg(target_os = "linux")]
-use crate::server::EpollListener;
+use super::config::{Config, IoBackend, ListenConfig, Overrides, TcpOptions};
+use super::serve_dir::ServeDir;
+use super::process::{self, PidFile};
+use crate::server::{
+    bind_socket, inherited_sockets, is_bound_to, signals, spawn_upgrade, started_by_upgrade,
+    ListenSocket, Listener, PollListener, ReusePort, Server,
+};
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use crate::server::UringListener;
+use crate::{error, info, logging, warn};
+
+/// How often the main thread checks for signals and finished workers
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Ports tried for each configured one when `port_scan` is enabled
+const PORT_SCAN_ATTEMPTS: usize = 100;
+/// How long `kang stop` waits beyond the shutdown timeout
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// The workers started for one server, and how to reach them on reload
+struct RunningServer {
+    listen: String,
+    reloaders: Vec<Sender<Server>>,
+}
+
+/// A listening socket ready to be handed to a server
+struct BoundSocket {
+    /// The address actually bound, which differs from the configured one with port_scan
+    listen: ListenConfig,
+    socket: ListenSocket,
+    /// Opened by systemd or the process kang replaces, rather than by kang
+    inherited: bool,
+}
 
 pub struct KangStarter;
 
 impl KangStarter {
-    fn is_port_available(port: u16) -> bool {
-        TcpListener::bind(format!("127.0.0.1:{}", port))
-            .or_else(|_| TcpListener::bind(format!("[::1]:{}", port)))
-            .is_ok()
+    /// Binds exactly the address of `listen`. With `port_scan`, a busy TCP port
+    /// is instead replaced by the first free one among the next `PORT_SCAN_ATTEMPTS`.
+    fn bind_address(
+        listen: &ListenConfig,
+        port_scan: bool,
+        reuse_port: ReusePort,
+        options: &TcpOptions,
+    ) -> io::Result<(ListenConfig, ListenSocket)> {
+        let port = match listen.host_port() {
+            Some((_, port)) if port_scan => port,
+            _ => return Ok((listen.clone(), bind_socket(listen, reuse_port, options)?)),
+        };
+        let mut last_error = None;
+
+        for candidate in (port..=u16::MAX).take(PORT_SCAN_ATTEMPTS) {
+            let listen = listen.with_port(candidate);
+            match bind_socket(&listen, reuse_port, options) {
+                Ok(socket) => {
+                    if candidate != port {
+                        warn!("Port {port} was in use, using port {candidate} instead");
+                    }
+                    return Ok((listen, socket));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::AddrInUse)))
+    }
+
+    /// Binds the socket of another worker. TCP workers bind their own socket
+    /// with SO_REUSEPORT. Workers share the first socket for Unix domain
+    /// sockets and inherited ones, which may not have SO_REUSEPORT set.
+    fn bind_worker_socket(first: &BoundSocket, options: &TcpOptions) -> io::Result<ListenSocket> {
+        if first.inherited || first.socket.is_unix() {
+            return first.socket.try_clone();
+        }
+        bind_socket(&first.listen, ReusePort::Join, options)
+    }
+
+    /// Returns the backend to run with, falling back to epoll if io_uring was
+    /// asked for but cannot be used.
+    fn io_backend(config: &Config) -> IoBackend {
+        if config.global.io_backend != IoBackend::IoUring {
+            return IoBackend::Epoll;
+        }
+
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            if UringListener::is_supported() {
+                info!("Using the io_uring backend");
+                return IoBackend::IoUring;
+            }
+            warn!("io_uring is not supported by this kernel, falling back to epoll");
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+        warn!("kang was built without the io_uring feature, falling back to epoll");
+
+        IoBackend::Epoll
+    }
+
+    fn new_listener(socket: ListenSocket, backend: IoBackend) -> io::Result<Box<dyn Listener>> {
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        if backend == IoBackend::IoUring {
+            return Ok(Box::new(UringListener::from_socket(socket)?));
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io_uring")))]
+        let _ = backend;
+
+        Ok(Box::new(PollListener::from_socket(socket)?))
     }
 
-    pub fn boot_servers(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let config = match Config::from_file(config_path) {
+    pub fn boot_servers(config_path: &str, overrides: &Overrides) -> Result<(), Box<dyn std::error::Error>> {
+        let config = match Config::load(config_path, overrides) {
             Ok(config) => config,
             Err(e) => {
                 error!("Failed to load config from {}: {}", config_path, e);
@@ -25,69 +127,146 @@ impl KangStarter {
             }
         };
 
+        Self::serve(config, Some((config_path, overrides)))
+    }
+
+    /// Serves a single directory with a config built from the command line.
+    /// There is no file to reload it from.
+    pub fn serve_dir(options: &ServeDir, overrides: &Overrides) -> Result<(), Box<dyn std::error::Error>> {
+        let mut config = options
+            .config()
+            .map_err(|e| format!("Cannot serve {}: {}", options.root, e))?;
+        overrides.apply(&mut config);
+        config.validate()?;
+
+        let address = ListenConfig::from_host_port(&options.host, options.port).address;
+        info!("Serving {} on http://{}", options.root, address);
+        Self::serve(config, None)
+    }
+
+    /// Binds and serves `config`. On SIGHUP the config is reloaded from `source`,
+    /// the file it was read from and the command line overrides.
+    fn serve(config: Config, source: Option<(&str, &Overrides)>) -> Result<(), Box<dyn std::error::Error>> {
+        logging::set_level(config.global.log_level);
+
         let servers = config.create_servers();
         if servers.is_empty() {
             error!("No valid servers found in configuration");
             return Err("No valid servers found".into());
         }
 
+        // Workers bind their own socket to the same address with SO_REUSEPORT
+        let worker_count = config.global.worker_processes.max(1);
+        let reuse_port = if worker_count > 1 {
+            info!("Starting {} workers per server", worker_count);
+            ReusePort::First
+        } else {
+            ReusePort::No
+        };
+
+        let backend = Self::io_backend(&config);
+        let port_scan = config.global.port_scan;
+        if port_scan {
+            warn!("port_scan is enabled, busy ports are replaced by the next free one");
+        }
+
+        // The kang handing its sockets to this one is still running
+        let upgraded = started_by_upgrade();
+        let pid_path = config.global.pid.as_deref().map(Path::new);
+        if let Some(path) = pid_path.filter(|_| !upgraded) {
+            PidFile::check_not_running(path)?;
+        }
+
+        // Sockets from systemd or a previous kang take the place of binding
+        let mut inherited = inherited_sockets();
+
+        // Bind every configured address before anything is started
+        let mut bound_servers = Vec::with_capacity(servers.len());
+        let mut failures = Vec::new();
+        let mut unix_paths: Vec<PathBuf> = Vec::new();
+        for server in servers {
+            let listen = &server.listen;
+            if let Some(index) = inherited.iter().position(|socket| is_bound_to(socket, listen)) {
+                info!("Using inherited socket for {}", listen.address);
+                let bound = BoundSocket {
+                    listen: listen.clone(),
+                    socket: inherited.swap_remove(index),
+                    inherited: true,
+                };
+                bound_servers.push((server, bound));
+                continue;
+            }
+
+            match Self::bind_address(listen, port_scan, reuse_port, &server.tcp_options) {
+                Ok((bound, socket)) => {
+                    unix_paths.extend(bound.unix_path().map(PathBuf::from));
+                    let bound = BoundSocket {
+                        listen: bound,
+                        socket,
+                        inherited: false,
+                    };
+                    bound_servers.push((server, bound));
+                }
+                Err(e) => {
+                    error!("Failed to bind {}: {}", listen.address, e);
+                    failures.push(format!("{} ({})", listen.address, e));
+                }
+            }
+        }
+
+        for socket in inherited {
+            warn!("Inherited socket {} matches no listen address, closing it", socket.local_addr());
+        }
+
+        if !failures.is_empty() {
+            Self::remove_unix_sockets(&unix_paths);
+            return Err(format!("Could not bind {}", failures.join(", ")).into());
+        }
+
+        // Sockets are bound, everything from here on may run unprivileged
+        let pid_file = match Self::prepare_process(&config, pid_path, upgraded, &unix_paths) {
+            Ok(pid_file) => pid_file,
+            Err(e) => {
+                Self::remove_unix_sockets(&unix_paths);
+                return Err(e.into());
+            }
+        };
+
+        signals::install()?;
+
         let mut handles = Vec::new();
-        let mut any_server_started = false;
-
-        // Create listeners for each server's ports
-        for mut server in servers {
-            let mut server_started = false;
-
-            'port_loop: for &original_port in &server.ports.clone() {
-                let mut current_port = original_port;
-                let max_attempts = 100; // Try up to 100 ports
-
-                // Try to find an available port
-                for attempt in 0..max_attempts {
-                    // Check if port is available before trying to bind
-                    if !Self::is_port_available(current_port) {
-                        warn!("Port {current_port} is in use, trying next port...");
-                        current_port += 1;
-                        continue;
-                    }
+        let mut running = Vec::new();
+        // Handed to the new process on upgrade
+        let mut listen_fds: Vec<RawFd> = Vec::new();
 
-                    let addr = format!("{host}:{port}", host = server.host, port = current_port);
-                    #[cfg(target_os = "macos")]
-                    let listener_result = KqueueListener::new(&addr);
-                    #[cfg(target_os = "linux")]
-                    let listener_result = EpollListener::new(&addr);
-
-                    match listener_result {
-                        Ok(listener) => {
-                            if current_port != original_port {
-                                warn!("Port {original_port} was in use, using port {current_port} instead");
-                            }
-                            match server.add_listener(listener) {
-                                Ok(_) => {
-                                    server_started = true;
-                                    break 'port_loop;
-                                }
-                                Err(e) => {
-                                    error!("Failed to add listener on port {}: {}", current_port, e);
-                                    continue;
-                                }
-                            }
-                        }
-                        Err(e) if attempt == max_attempts - 1 => {
-                            error!("Failed to bind to any port for server {}: {}", server.host, e);
-                        }
-                        Err(_) => {
-                            current_port += 1;
-                            continue;
-                        }
-                    }
+        for (mut server, bound) in bound_servers {
+            let mut workers = Vec::with_capacity(worker_count);
+            for _ in 1..worker_count {
+                let mut worker = server.worker();
+                let result = Self::bind_worker_socket(&bound, &server.tcp_options)
+                    .and_then(|socket| Self::new_listener(socket, backend))
+                    .and_then(|listener| worker.add_listener(listener));
+                match result {
+                    Ok(_) => workers.push(worker),
+                    Err(e) => error!("Failed to start worker on {}: {}", server.address(), e),
                 }
             }
+            listen_fds.push(bound.socket.as_raw_fd());
+            server.add_listener(Self::new_listener(bound.socket, backend)?)?;
+            workers.push(server);
+
+            let mut reloaders = Vec::with_capacity(workers.len());
+            for worker in workers.iter_mut() {
+                reloaders.push(worker.reload_channel());
+            }
+            running.push(RunningServer {
+                listen: workers[0].listen.address.clone(),
+                reloaders,
+            });
 
-            if server_started {
-                any_server_started = true;
+            for mut worker in workers {
                 let handle = thread::spawn(move || {
-                    if let Err(e) = server.listen_and_serve() {
+                    if let Err(e) = worker.listen_and_serve() {
                         error!("Server error: {}", e);
                     }
                 });
@@ -95,18 +274,227 @@ impl KangStarter {
             }
         }
 
-        if !any_server_started {
-            error!("Failed to start any servers");
-            return Err("No servers could be started".into());
+        // Serve until every worker has stopped, reloading on SIGHUP and
+        // upgrading on SIGUSR2
+        let mut upgrade: Option<Child> = None;
+        while handles.iter().any(|handle| !handle.is_finished()) {
+            if signals::take_reload_request() {
+                match source {
+                    Some((config_path, overrides)) => Self::reload(config_path, overrides, &running),
+                    None => warn!("No config file to reload, the configuration came from the command line"),
+                }
+            }
+            if signals::take_upgrade_request() && !signals::shutdown_requested() {
+                Self::upgrade(&listen_fds, &mut upgrade);
+            }
+            if let Some(child) = upgrade.as_mut() {
+                if let Ok(Some(status)) = child.try_wait() {
+                    error!("New kang process exited with {}, still serving", status);
+                    upgrade = None;
+                    if let Some(Err(e)) = pid_file.as_ref().map(PidFile::write) {
+                        warn!("Could not restore the pid file: {}", e);
+                    }
+                }
+            }
+            thread::sleep(SIGNAL_POLL_INTERVAL);
         }
 
-        // Wait for all server threads to complete
         for handle in handles {
             if let Err(e) = handle.join() {
                 error!("Server thread panicked: {:?}", e);
             }
         }
+        // The new process still listens on the socket files
+        if upgrade.is_none() {
+            Self::remove_unix_sockets(&unix_paths);
+        }
+        if let Some(pid_file) = pid_file {
+            pid_file.remove();
+        }
+
+        Ok(())
+    }
+
+    /// Detaches in daemon mode, redirects logs, writes the pid file and drops
+    /// privileges, in that order. Must run before any thread is started.
+    ///
+    /// A kang started by an upgrade is already detached, and may have lost the
+    /// privileges needed to write the pid file.
+    fn prepare_process(
+        config: &Config,
+        pid_path: Option<&Path>,
+        upgraded: bool,
+        unix_paths: &[PathBuf],
+    ) -> io::Result<Option<PidFile>> {
+        let global = &config.global;
+        let log = global.log_file.as_deref().map(process::open_log).transpose()?;
+
+        if global.daemon && !upgraded {
+            match &global.log_file {
+                Some(path) => info!("Running in the background, logging to {}", path),
+                None => info!("Running in the background, logs are discarded"),
+            }
+            process::daemonize()?;
+        }
+
+        match log {
+            Some(file) => process::redirect_logs(&file)?,
+            None if global.daemon => {
+                process::redirect_logs(&OpenOptions::new().write(true).open("/dev/null")?)?
+            }
+            None => {}
+        }
+
+        let pid_file = match pid_path.map(PidFile::create) {
+            Some(Ok(pid_file)) => Some(pid_file),
+            Some(Err(e)) if upgraded => {
+                warn!("Could not write the pid file: {}", e);
+                None
+            }
+            Some(Err(e)) => return Err(e),
+            None => None,
+        };
+
+        // Files removed on exit must stay removable by the new user
+        let owned: Vec<&Path> = pid_file
+            .as_ref()
+            .and(pid_path)
+            .into_iter()
+            .chain(unix_paths.iter().map(PathBuf::as_path))
+            .collect();
+        process::drop_privileges(global.user.as_deref(), global.group.as_deref(), &owned)?;
+        Ok(pid_file)
+    }
+
+    /// Loads and validates the config as `boot_servers` would, without binding
+    /// anything. Every problem found is logged.
+    pub fn test_config(config_path: &str, overrides: &Overrides) -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config::load(config_path, overrides)
+            .map_err(|e| format!("Configuration file {} test failed: {}", config_path, e))?;
+        if config.create_servers().is_empty() {
+            return Err(format!("Configuration file {} test failed: no servers configured", config_path).into());
+        }
+
+        info!("Configuration file {} test is successful", config_path);
+        Ok(())
+    }
+
+    /// Asks the kang named in the pid file of the config to shut down, and
+    /// waits for it to finish draining its connections.
+    pub fn stop(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config::from_file(config_path)?;
+        let pid = Self::signal_running(&config, libc::SIGTERM)?;
+        info!("Sent SIGTERM to kang (pid {}), waiting for it to exit", pid);
+
+        let deadline = Instant::now() + Duration::from_secs(config.global.shutdown_timeout) + STOP_GRACE;
+        while process::is_running(pid) {
+            if Instant::now() >= deadline {
+                return Err(format!("kang (pid {}) did not exit in time", pid).into());
+            }
+            thread::sleep(SIGNAL_POLL_INTERVAL);
+        }
 
+        info!("kang stopped");
         Ok(())
     }
+
+    /// Asks the kang named in the pid file of the config to reload its configuration.
+    pub fn signal_reload(config_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config::from_file(config_path)?;
+        let pid = Self::signal_running(&config, libc::SIGHUP)?;
+        info!("Sent SIGHUP to kang (pid {}) to reload its configuration", pid);
+        Ok(())
+    }
+
+    /// Sends `signal` to the kang whose PID is in `global.pid`.
+    fn signal_running(config: &Config, signal: libc::c_int) -> Result<libc::pid_t, Box<dyn std::error::Error>> {
+        let path = config
+            .global
+            .pid
+            .as_deref()
+            .ok_or("global.pid is not set, the running kang cannot be found")?;
+
+        let pid = process::read_pid(Path::new(path))
+            .map_err(|e| format!("Could not read pid file {}: {}", path, e))?;
+        if !process::is_running(pid) {
+            return Err(format!("kang is not running (stale pid {} in {})", pid, path).into());
+        }
+
+        if unsafe { libc::kill(pid, signal) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(pid)
+    }
+
+    /// Starts a new kang from the binary on disk with the listening sockets. Both
+    /// accept connections until this process is stopped with SIGTERM.
+    fn upgrade(listen_fds: &[RawFd], upgrade: &mut Option<Child>) {
+        if upgrade.is_some() {
+            warn!("An upgrade is already in progress");
+            return;
+        }
+
+        match spawn_upgrade(listen_fds) {
+            Ok(child) => {
+                info!("Started new kang process {} with the listening sockets", child.id());
+                *upgrade = Some(child);
+            }
+            Err(e) => error!("Upgrade failed, still serving: {}", e),
+        }
+    }
+
+    /// Removes the socket files of Unix domain sockets once nothing listens on them.
+    fn remove_unix_sockets(paths: &[PathBuf]) {
+        for path in paths {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Could not remove {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Re-reads the config and hands every running server its new virtual hosts.
+    /// Listening sockets stay open; if the new config is invalid the old one keeps running.
+    /// Command line overrides still apply to the new config.
+    fn reload(config_path: &str, overrides: &Overrides, running: &[RunningServer]) {
+        info!("Reloading configuration from {}", config_path);
+
+        let config = match Config::load(config_path, overrides) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Reload failed, keeping the current configuration: {}", e);
+                return;
+            }
+        };
+        logging::set_level(config.global.log_level);
+
+        let servers = config.create_servers();
+        for server in running {
+            let reloaded = servers.iter().find(|candidate| candidate.listens_on(&server.listen));
+
+            match reloaded {
+                Some(reloaded) => {
+                    for reloader in &server.reloaders {
+                        // Workers that already stopped have dropped their receiver
+                        let _ = reloader.send(reloaded.worker());
+                    }
+                }
+                None => warn!(
+                    "{} is no longer configured, keeping its current configuration",
+                    server.listen
+                ),
+            }
+        }
+
+        for server in &servers {
+            let is_running = running
+                .iter()
+                .any(|r| server.listens_on(&r.listen));
+            if !is_running {
+                warn!(
+                    "New listen address {} needs a restart to take effect",
+                    server.address()
+                );
+            }
+        }
+    }
 }
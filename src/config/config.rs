@@ -2,7 +2,9 @@ use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fs, path::Path};
 
 use super::{errors::ConfigError, validator::ConfigValidator};
-use crate::server::Server;
+use crate::http::RequestLimits;
+use crate::logging::LogLevel;
+use crate::server::{Server, VirtualHost};
 
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct SessionConfig {
@@ -24,6 +26,120 @@ fn default_cookie_path() -> String { "/".to_string() }
 fn default_cookie_secure() -> bool { false }
 fn default_cookie_http_only() -> bool { true }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimeoutsConfig {
+    /// Seconds a client may take to send a complete request
+    #[serde(default = "default_read_timeout")]
+    pub read: u64,
+    /// Seconds a response may go without the client reading any of it
+    #[serde(default = "default_write_timeout")]
+    pub write: u64,
+    /// Seconds an idle keep-alive connection is kept open, 0 disables keep-alive
+    #[serde(default = "default_keep_alive_timeout")]
+    pub keep_alive: u64,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        TimeoutsConfig {
+            read: default_read_timeout(),
+            write: default_write_timeout(),
+            keep_alive: default_keep_alive_timeout(),
+        }
+    }
+}
+
+/// Size limits on the request line and headers, checked before a request is routed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RequestLimitsConfig {
+    /// Bytes in the request line, 414 beyond
+    #[serde(default = "default_max_request_line")]
+    pub max_request_line: usize,
+    /// Bytes in the header section, 431 beyond
+    #[serde(default = "default_max_header_size")]
+    pub max_header_size: usize,
+    /// Header fields in a request, 431 beyond
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+}
+
+impl Default for RequestLimitsConfig {
+    fn default() -> Self {
+        RequestLimitsConfig {
+            max_request_line: default_max_request_line(),
+            max_header_size: default_max_header_size(),
+            max_headers: default_max_headers(),
+        }
+    }
+}
+
+fn default_max_request_line() -> usize { RequestLimits::default().max_request_line }
+fn default_max_header_size() -> usize { RequestLimits::default().max_header_size }
+fn default_max_headers() -> usize { RequestLimits::default().max_headers }
+
+fn default_read_timeout() -> u64 { 60 }
+fn default_write_timeout() -> u64 { 60 }
+fn default_keep_alive_timeout() -> u64 { 75 }
+fn default_keep_alive_requests() -> usize { 100 }
+fn default_worker_processes() -> usize { 1 }
+fn default_worker_connections() -> usize { 1024 }
+fn default_shutdown_timeout() -> u64 { 30 }
+
+/// Socket options applied to listening sockets and the connections they accept.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Send small writes right away instead of coalescing them (Nagle's algorithm)
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// Probe idle connections so dead peers are detected
+    #[serde(default)]
+    pub so_keepalive: bool,
+    /// Seconds of idleness before the first keep-alive probe
+    pub keepalive_idle: Option<u32>,
+    /// Seconds between keep-alive probes
+    pub keepalive_interval: Option<u32>,
+    /// Unanswered probes after which the connection is dropped
+    pub keepalive_count: Option<u32>,
+    /// Allow binding while connections of a previous process linger in TIME_WAIT
+    #[serde(default = "default_so_reuseaddr")]
+    pub so_reuseaddr: bool,
+    /// Receive buffer size in bytes, the system default when unset
+    pub so_rcvbuf: Option<u32>,
+    /// Send buffer size in bytes, the system default when unset
+    pub so_sndbuf: Option<u32>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            tcp_nodelay: default_tcp_nodelay(),
+            so_keepalive: false,
+            keepalive_idle: None,
+            keepalive_interval: None,
+            keepalive_count: None,
+            so_reuseaddr: default_so_reuseaddr(),
+            so_rcvbuf: None,
+            so_sndbuf: None,
+        }
+    }
+}
+
+fn default_tcp_nodelay() -> bool { true }
+fn default_so_reuseaddr() -> bool { true }
+
+/// How connections are driven.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IoBackend {
+    /// Readiness notifications: epoll on Linux, kqueue on macOS
+    #[default]
+    #[serde(alias = "kqueue")]
+    Epoll,
+    /// Completion queues through io_uring, Linux only and when built with the
+    /// `io_uring` feature. Falls back to epoll when unavailable.
+    IoUring,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub global: GlobalConfig,
@@ -32,6 +148,35 @@ pub struct Config {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GlobalConfig {
+    /// Worker threads started per server, each with its own event loop
+    #[serde(default = "default_worker_processes")]
+    pub worker_processes: usize,
+    /// Connections a single worker keeps open at once
+    #[serde(default = "default_worker_connections")]
+    pub worker_connections: usize,
+    /// Seconds active connections get to finish after SIGTERM/SIGINT
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    #[serde(default)]
+    pub io_backend: IoBackend,
+    /// Development only: when a configured port is busy, use the next free one
+    /// instead of failing
+    #[serde(default)]
+    pub port_scan: bool,
+    /// File the PID of the running kang is written to, used by `kang stop`
+    pub pid: Option<String>,
+    /// User to switch to once listening sockets are bound, when started as root
+    pub user: Option<String>,
+    /// Group to switch to, the primary group of `user` by default
+    pub group: Option<String>,
+    /// Detach from the terminal and run in the background
+    #[serde(default)]
+    pub daemon: bool,
+    /// File logs are appended to. Daemons without one discard their logs.
+    pub log_file: Option<String>,
+    /// Least severe messages logged: debug, info, warn or error
+    #[serde(default)]
+    pub log_level: LogLevel,
     pub client_max_body_size: Option<String>,
     pub response_format: Option<String>,
     pub cgi: HashMap<String, String>,
@@ -45,11 +190,103 @@ pub struct ErrorPages {
     pub pages: HashMap<String, String>,
 }
 
+/// An address a server listens on: `addr:port`, with IPv6 addresses in
+/// brackets as in `[::]:8080`, or `unix:/path/to/socket`.
+///
+/// Written either as a plain string or as an object carrying per-listen
+/// options, e.g. `{ "address": "[::]:8080", "ipv6_only": false }`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(from = "ListenEntry")]
+pub struct ListenConfig {
+    pub address: String,
+    /// Length of the queue of connections waiting to be accepted, SOMAXCONN by default
+    pub backlog: Option<i32>,
+    /// Whether an IPv6 socket only accepts IPv6. Set to false to also accept
+    /// IPv4 connections on `[::]`, as IPv4-mapped addresses.
+    pub ipv6_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListenEntry {
+    Address(String),
+    Options {
+        address: String,
+        #[serde(default)]
+        backlog: Option<i32>,
+        #[serde(default = "default_ipv6_only")]
+        ipv6_only: bool,
+    },
+}
+
+fn default_ipv6_only() -> bool { true }
+
+impl From<ListenEntry> for ListenConfig {
+    fn from(entry: ListenEntry) -> Self {
+        match entry {
+            ListenEntry::Address(address) => ListenConfig::new(address),
+            ListenEntry::Options { address, backlog, ipv6_only } => ListenConfig {
+                address,
+                backlog,
+                ipv6_only,
+            },
+        }
+    }
+}
+
+impl ListenConfig {
+    pub fn new(address: impl Into<String>) -> Self {
+        ListenConfig {
+            address: address.into(),
+            backlog: None,
+            ipv6_only: default_ipv6_only(),
+        }
+    }
+
+    /// Listens on `host:port`, bracketing `host` if it is an IPv6 address.
+    pub fn from_host_port(host: &str, port: u16) -> Self {
+        if host.contains(':') && !host.starts_with('[') {
+            ListenConfig::new(format!("[{}]:{}", host, port))
+        } else {
+            ListenConfig::new(format!("{}:{}", host, port))
+        }
+    }
+
+    /// The socket path of a `unix:` address.
+    pub fn unix_path(&self) -> Option<&Path> {
+        self.address.strip_prefix("unix:").map(Path::new)
+    }
+
+    /// The host and port of a TCP address.
+    pub fn host_port(&self) -> Option<(&str, u16)> {
+        if self.unix_path().is_some() {
+            return None;
+        }
+        let (host, port) = self.address.rsplit_once(':')?;
+        Some((host, port.parse().ok()?))
+    }
+
+    /// The same listen options on another port.
+    pub fn with_port(&self, port: u16) -> Self {
+        let host = self.host_port().map_or("", |(host, _)| host);
+        ListenConfig {
+            address: format!("{}:{}", host, port),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerConfig {
     pub server_name: Vec<String>,
     pub error_pages: ErrorPages,
+    /// Addresses to listen on
+    #[serde(default)]
+    pub listen: Vec<ListenConfig>,
+    /// Shorthand for listening on `host` at each of `ports`
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub ports: Vec<u16>,
     #[serde(default)]
     pub is_default: bool,
@@ -57,6 +294,18 @@ pub struct ServerConfig {
     pub client_max_body_size: Option<String>,
     #[serde(default)]
     pub sessions: SessionConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    #[serde(default)]
+    pub request_limits: RequestLimitsConfig,
+    #[serde(default = "default_keep_alive_requests")]
+    pub keep_alive_requests: usize,
+    /// Default accept queue length of the `listen` entries that set none
+    pub backlog: Option<i32>,
+    /// Connections open at once across every worker, unlimited when unset
+    pub max_connections: Option<usize>,
+    #[serde(default)]
+    pub tcp_options: TcpOptions,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -73,6 +322,64 @@ pub struct RouteConfig {
     pub client_max_body_size: Option<String>,
     #[serde(default)]
     pub sessions_required: bool,
+    /// Directory POSTed files are saved to, `<root>uploads` by default
+    pub upload_dir: Option<String>,
+    /// Serve the index file for paths matching no file, so a single page
+    /// app can handle its own routes
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// Allow cross-origin requests, answering preflights with the route's methods
+    pub cors: Option<CorsConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CorsConfig {
+    #[serde(default = "default_enabled_true")]
+    pub enabled: bool,
+    /// Origins allowed to read responses, `*` for any
+    #[serde(default = "default_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Methods allowed in preflights, the methods of the route by default
+    #[serde(default)]
+    pub allowed_methods: Vec<String>,
+    /// Request headers allowed in preflights, whatever the browser asks for by default
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    /// Seconds browsers may cache a preflight response
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            enabled: true,
+            allowed_origins: default_allowed_origins(),
+            allowed_methods: Vec::new(),
+            allowed_headers: Vec::new(),
+            max_age: None,
+        }
+    }
+}
+
+fn default_enabled_true() -> bool { true }
+fn default_allowed_origins() -> Vec<String> { vec!["*".to_string()] }
+
+impl ServerConfig {
+    /// Every address this server block listens on: its `listen` entries
+    /// followed by `host` on each of `ports`. Entries without a backlog take
+    /// the one of the server block.
+    pub fn listen_addresses(&self) -> Vec<ListenConfig> {
+        let mut addresses = self.listen.clone();
+        addresses.extend(
+            self.ports
+                .iter()
+                .map(|&port| ListenConfig::from_host_port(&self.host, port)),
+        );
+        for listen in addresses.iter_mut() {
+            listen.backlog = listen.backlog.or(self.backlog);
+        }
+        addresses
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -81,22 +388,81 @@ pub struct RedirectConfig {
     pub code: u16,
 }
 
+/// Settings given on the command line, which take precedence over the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// Replaces the port of every TCP address
+    pub port: Option<u16>,
+    pub log_level: Option<LogLevel>,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(level) = self.log_level {
+            config.global.log_level = level;
+        }
+
+        if let Some(port) = self.port {
+            for server in config.servers.iter_mut() {
+                if !server.ports.is_empty() {
+                    server.ports = vec![port];
+                }
+
+                let mut listen: Vec<ListenConfig> = Vec::new();
+                for entry in &server.listen {
+                    let entry = match entry.host_port() {
+                        Some(_) => entry.with_port(port),
+                        None => entry.clone(),
+                    };
+                    // Addresses that only differed by port are now the same
+                    if !listen.iter().any(|seen| seen.address == entry.address) {
+                        listen.push(entry);
+                    }
+                }
+                server.listen = listen;
+            }
+        }
+    }
+}
+
 impl Config {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
-        let contents = fs::read_to_string(path)?;
-        let config: Config = serde_json::from_str(&contents)?;
+        Self::load(path, &Overrides::default())
+    }
 
-        if let Err(e) = ConfigValidator::validate(&config) {
-            return Err(ConfigError::ValidationError(e));
-        }
+    /// Reads the config file, applies the command line `overrides` and validates the result.
+    pub fn load<P: AsRef<Path>>(path: P, overrides: &Overrides) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        let mut config: Config = serde_json::from_str(&contents)?;
+        overrides.apply(&mut config);
 
+        config.validate()?;
         Ok(config)
     }
 
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ConfigValidator::validate(self).map_err(ConfigError::ValidationError)
+    }
+
+    /// Creates one server per address. Server blocks sharing a listen address
+    /// become virtual hosts of the same server.
     pub fn create_servers(&self) -> Vec<Server> {
-        self.servers
-            .iter()
-            .map(|server_config| Server::new(server_config.clone(), self.clone()))
-            .collect()
+        let mut servers: Vec<Server> = Vec::new();
+
+        for server_config in &self.servers {
+            // Built once, so every address of the block shares its sessions
+            let vhost = VirtualHost::new(server_config.clone(), self.clone());
+            for listen in server_config.listen_addresses() {
+                match servers
+                    .iter_mut()
+                    .find(|server| server.listens_on(&listen.address))
+                {
+                    Some(server) => server.add_vhost(vhost.clone()),
+                    None => servers.push(Server::new(server_config, listen, vhost.clone(), self)),
+                }
+            }
+        }
+
+        servers
     }
 }
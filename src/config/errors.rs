@@ -1,6 +1,7 @@
 use thiserror::Error;
 
 #[derive(Debug, Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ConfigError {
     IoError(#[from] std::io::Error),
     ParseError(#[from] serde_json::Error),
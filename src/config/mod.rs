@@ -1,7 +1,11 @@
 pub mod boot;
+#[allow(clippy::module_inception)]
 pub mod config;
 
 mod errors;
+pub mod process;
+mod serve_dir;
 mod validator;
 
 pub use config::*;
+pub use serve_dir::ServeDir;
@@ -0,0 +1,221 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::{info, warn};
+
+/// The file holding the PID of the running kang, see `global.pid`.
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl PidFile {
+    /// Fails if the file names a kang that is still running.
+    pub fn check_not_running(path: &Path) -> io::Result<()> {
+        match read_pid(path) {
+            Ok(pid) if is_running(pid) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("kang is already running with pid {} ({})", pid, path.display()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the PID of this process to `path`.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let pid_file = PidFile {
+            path: path.to_path_buf(),
+            pid: std::process::id(),
+        };
+        pid_file.write()?;
+        Ok(pid_file)
+    }
+
+    /// Writes the PID again, after a process that replaced it in the file exited.
+    pub fn write(&self) -> io::Result<()> {
+        fs::write(&self.path, format!("{}\n", self.pid))
+    }
+
+    /// Removes the file, unless another process has written its PID to it since.
+    pub fn remove(&self) {
+        if read_pid(&self.path).ok() != Some(self.pid as libc::pid_t) {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Could not remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+pub fn read_pid(path: &Path) -> io::Result<libc::pid_t> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{} holds no pid", path.display())))
+}
+
+/// Returns true if a process with this PID exists, even one we may not signal.
+pub fn is_running(pid: libc::pid_t) -> bool {
+    pid > 0
+        && (unsafe { libc::kill(pid, 0) } == 0
+            || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+/// Detaches from the terminal: forks twice so kang is neither a session leader
+/// nor the child of the shell, and reads from /dev/null. The original process
+/// exits. Must be called before any thread is started.
+pub fn daemonize() -> io::Result<()> {
+    fork_and_exit_parent()?;
+    if unsafe { libc::setsid() } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    fork_and_exit_parent()?;
+
+    let null = File::open("/dev/null")?;
+    if unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn fork_and_exit_parent() -> io::Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Opens the file logs are appended to, see `redirect_logs`.
+pub fn open_log(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Sends everything logged, on stdout and stderr, to `file`.
+pub fn redirect_logs(file: &File) -> io::Result<()> {
+    for target in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(file.as_raw_fd(), target) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Switches to `user` and `group` once the listening sockets are bound. The
+/// group defaults to the user's primary group. Only possible when started as
+/// root; otherwise the process keeps running as whoever started it.
+///
+/// `owned` are files created as root that kang removes on exit, such as the
+/// pid file; they are handed to the new user first.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>, owned: &[&Path]) -> io::Result<()> {
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+
+    let account = user.map(lookup_user).transpose()?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => account.as_ref().map_or(unsafe { libc::getgid() }, |account| account.gid),
+    };
+
+    if unsafe { libc::geteuid() } != 0 {
+        let uid = account.as_ref().map_or(unsafe { libc::getuid() }, |account| account.uid);
+        if uid != unsafe { libc::getuid() } || gid != unsafe { libc::getgid() } {
+            warn!("Not started as root, user and group are ignored");
+        }
+        return Ok(());
+    }
+
+    // chown leaves the owner unchanged for -1, when only the group changes
+    let uid = account.as_ref().map_or(libc::uid_t::MAX, |account| account.uid);
+    for path in owned {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path contains a NUL byte"))?;
+        if unsafe { libc::chown(c_path.as_ptr(), uid, gid) } < 0 {
+            warn!("Could not hand {} to the new user: {}", path.display(), io::Error::last_os_error());
+        }
+    }
+
+    // Supplementary groups of root are dropped before the group itself
+    match &account {
+        Some(account) => {
+            if unsafe { libc::initgroups(account.name.as_ptr(), gid as _) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        None => {
+            if unsafe { libc::setgroups(1, &gid) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    if unsafe { libc::setgid(gid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if account.is_some() && unsafe { libc::setuid(uid) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    info!(
+        "Running as user {} and group {}",
+        user.unwrap_or("root"),
+        group.map_or(gid.to_string(), str::to_string)
+    );
+    Ok(())
+}
+
+struct Account {
+    name: CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+fn lookup_user(name: &str) -> io::Result<Account> {
+    let c_name = CString::new(name).map_err(|_| unknown("user", name))?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+
+    let status = unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    if result.is_null() {
+        return Err(unknown("user", name));
+    }
+
+    Ok(Account {
+        name: c_name,
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+    })
+}
+
+fn lookup_group(name: &str) -> io::Result<libc::gid_t> {
+    let c_name = CString::new(name).map_err(|_| unknown("group", name))?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+
+    let status = unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if status != 0 {
+        return Err(io::Error::from_raw_os_error(status));
+    }
+    if result.is_null() {
+        return Err(unknown("group", name));
+    }
+    Ok(group.gr_gid)
+}
+
+fn unknown(kind: &str, name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("Unknown {} '{}'", kind, name))
+}
@@ -0,0 +1,82 @@
+use serde_json::json;
+use std::{fs, io};
+
+use super::Config;
+
+/// Largest upload `kang serve-dir --upload` accepts
+const UPLOAD_MAX_BODY_SIZE: &str = "100M";
+
+/// Options of `kang serve-dir`, which serves one directory without a config file.
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    pub root: String,
+    pub host: String,
+    pub port: u16,
+    /// List directories without an index.html
+    pub listing: bool,
+    /// Answer paths matching no file with the root index.html
+    pub spa: bool,
+    /// Allow cross-origin requests from any origin
+    pub cors: bool,
+    /// Save files POSTed as multipart form data to the root
+    pub upload: bool,
+}
+
+impl Default for ServeDir {
+    fn default() -> Self {
+        ServeDir {
+            root: ".".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            listing: false,
+            spa: false,
+            cors: false,
+            upload: false,
+        }
+    }
+}
+
+impl ServeDir {
+    /// Builds a config with a single server and a single route serving the directory.
+    pub fn config(&self) -> io::Result<Config> {
+        let root = fs::canonicalize(&self.root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", self.root),
+            ));
+        }
+        let root = root.to_string_lossy().into_owned();
+
+        let mut methods = vec!["GET", "HEAD"];
+        if self.upload {
+            methods.push("POST");
+        }
+
+        let config = json!({
+            "global": {
+                "cgi": {},
+            },
+            "servers": [{
+                "server_name": ["localhost"],
+                "error_pages": {},
+                "host": self.host,
+                "ports": [self.port],
+                "is_default": true,
+                "routes": [{
+                    "path": "/",
+                    "root": root,
+                    "index": "index.html",
+                    "methods": methods,
+                    "directory_listing": self.listing,
+                    "spa_fallback": self.spa,
+                    "cors": self.cors.then(|| json!({})),
+                    "upload_dir": self.upload.then_some(&root),
+                    "client_max_body_size": self.upload.then_some(UPLOAD_MAX_BODY_SIZE),
+                }],
+            }],
+        });
+
+        serde_json::from_value(config).map_err(io::Error::other)
+    }
+}
@@ -1,9 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use thiserror::Error;
 
-use super::Config;
+use super::{Config, ListenConfig};
 
+use crate::http::status::StatusCode;
+use crate::server::is_valid_server_name;
 use crate::{error, warn};
 
 /// Valid HTTP methods according to RFC 7231 and common extensions
@@ -27,6 +29,8 @@ pub enum ValidatorError {
     InvalidCGIPath(String, String),
     #[error("Invalid host: {0}")]
     InvalidHost(String),
+    #[error("Invalid listen address: {0}")]
+    InvalidListenAddress(String),
     #[error("Error page not found: {0}")]
     ErrorPageNotFound(String),
     #[error("Invalid route methods: {0}")]
@@ -35,14 +39,18 @@ pub enum ValidatorError {
     InvalidRoutePath(String),
     #[error("Invalid root: {0}")]
     InvalidRoot(String),
-    #[error("Duplicate port {0} found across servers")]
-    DuplicatePort(u16),
+    #[error("More than one default server for {0}")]
+    DuplicateDefaultServer(String),
     #[error("Duplicate route path {0} found in server")]
     DuplicateRoute(String),
-    #[error("Redirect code {0} is not a valid HTTP redirect status code")]
+    #[error("Redirect code {0} is not a valid HTTP status code")]
     InvalidRedirectCode(u16),
     #[error("Invalid client_max_body_size format: {0}")]
     InvalidBodySizeFormat(String),
+    #[error("{0} must be at least 1")]
+    InvalidWorkerSetting(&'static str),
+    #[error("{0} must be at least 1 in server {1}")]
+    InvalidServerSetting(&'static str, String),
 }
 
 pub struct ConfigValidator;
@@ -62,11 +70,46 @@ impl ConfigValidator {
         Ok(())
     }
 
+    /// Checks that a listen entry is `unix:` followed by a path, or a host and port
+    fn validate_listen(listen: &ListenConfig) -> Result<(), ValidatorError> {
+        let invalid = || ValidatorError::InvalidListenAddress(listen.address.clone());
+
+        match listen.unix_path() {
+            Some(path) if path.as_os_str().is_empty() => return Err(invalid()),
+            Some(_) => {}
+            None => match listen.host_port() {
+                Some((host, _)) if !host.is_empty() => {}
+                _ => return Err(invalid()),
+            },
+        }
+
+        if listen.backlog.is_some_and(|backlog| backlog < 1) {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+
+    /// Validates the whole configuration, warning on recoverable issues.
+    /// Every critical error is logged before the first one is returned, so a
+    /// single run reports all of them.
     pub fn validate(config: &Config) -> Result<(), ValidatorError> {
-        let mut used_ports = HashSet::new();
-        let mut has_critical_error = false;
+        // Server names and default flag seen on each listen address
+        let mut addresses: HashMap<String, (HashSet<String>, bool)> = HashMap::new();
+        let mut critical: Vec<ValidatorError> = Vec::new();
 
         // Validate global config
+        if config.global.worker_processes == 0 {
+            error!("worker_processes must be at least 1");
+            critical.push(ValidatorError::InvalidWorkerSetting("worker_processes"));
+        }
+        if config.global.worker_connections == 0 {
+            error!("worker_connections must be at least 1");
+            critical.push(ValidatorError::InvalidWorkerSetting("worker_connections"));
+        }
+        if config.global.daemon && config.global.pid.is_none() {
+            warn!("daemon is enabled without a pid file, kang stop will not find the daemon");
+        }
+
         if let Some(size) = &config.global.client_max_body_size {
             if let Err(e) = Self::validate_body_size(size) {
                 warn!("Invalid global client_max_body_size: {}", e);
@@ -76,37 +119,107 @@ impl ConfigValidator {
         // Validate each server configuration
         for server in &config.servers {
             // Validate host (critical)
-            if server.host.is_empty() {
+            if !server.ports.is_empty() && server.host.is_empty() {
                 error!("Server has empty host");
-                has_critical_error = true;
+                critical.push(ValidatorError::InvalidHost(String::new()));
                 continue;
             }
 
-            // Validate server names (warning)
+            let listen = server.listen_addresses();
+            let label = listen
+                .iter()
+                .map(|listen| listen.address.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ");
+
+            // Validate server names (warning for empty names, critical for malformed wildcards)
             for name in &server.server_name {
                 if name.is_empty() {
-                    warn!("Empty server name in server {}", server.host);
+                    warn!("Empty server name in server {}", label);
+                } else if !is_valid_server_name(name) {
+                    error!("Invalid server name '{}' in server {}", name, label);
+                    critical.push(ValidatorError::InvalidServerName(name.clone()));
                 }
             }
 
-            // Validate ports (critical for duplicates)
-            if server.ports.is_empty() {
-                error!("No ports specified for server {}", server.host);
-                has_critical_error = true;
+            // Validate listen addresses (critical)
+            if listen.is_empty() {
+                error!("No listen addresses or ports specified for server {:?}", server.server_name);
+                critical.push(ValidatorError::NoPortsSpecified);
                 continue;
             }
+            let invalid_listen: Vec<ValidatorError> =
+                listen.iter().filter_map(|entry| Self::validate_listen(entry).err()).collect();
+            if !invalid_listen.is_empty() {
+                for e in invalid_listen {
+                    error!("{}", e);
+                    critical.push(e);
+                }
+                continue;
+            }
+
+            // Validate connection settings (critical)
+            let tcp = &server.tcp_options;
+            let settings = [
+                ("backlog", server.backlog.map(|backlog| backlog as i64)),
+                ("max_connections", server.max_connections.map(|max| max as i64)),
+                ("request_limits.max_request_line", Some(server.request_limits.max_request_line as i64)),
+                ("request_limits.max_header_size", Some(server.request_limits.max_header_size as i64)),
+                ("request_limits.max_headers", Some(server.request_limits.max_headers as i64)),
+                ("tcp_options.keepalive_idle", tcp.keepalive_idle.map(i64::from)),
+                ("tcp_options.keepalive_interval", tcp.keepalive_interval.map(i64::from)),
+                ("tcp_options.keepalive_count", tcp.keepalive_count.map(i64::from)),
+                ("tcp_options.so_rcvbuf", tcp.so_rcvbuf.map(i64::from)),
+                ("tcp_options.so_sndbuf", tcp.so_sndbuf.map(i64::from)),
+            ];
+            for (setting, value) in settings {
+                if value.is_some_and(|value| value < 1) {
+                    error!("{} must be at least 1 in server {}", setting, label);
+                    critical.push(ValidatorError::InvalidServerSetting(setting, label.clone()));
+                }
+            }
+
+            let keepalive_tuned = tcp.keepalive_idle.is_some()
+                || tcp.keepalive_interval.is_some()
+                || tcp.keepalive_count.is_some();
+            if keepalive_tuned && !tcp.so_keepalive {
+                warn!("TCP keep-alive settings in server {} have no effect without so_keepalive", label);
+            }
+
+            if let Some(max) = server.max_connections {
+                let reachable = config.global.worker_connections.saturating_mul(config.global.worker_processes);
+                if max > reachable {
+                    warn!(
+                        "max_connections {} in server {} exceeds worker_connections x worker_processes ({})",
+                        max, label, reachable
+                    );
+                }
+            }
+
+            // Several servers may share an address as virtual hosts, but only one can be the default
+            for entry in &listen {
+                let address = entry.address.clone();
+                let (names, has_default) = addresses.entry(address.clone()).or_default();
 
-            for &port in &server.ports {
-                if !used_ports.insert(port) {
-                    error!("Duplicate port {} found across servers", port);
-                    return Err(ValidatorError::DuplicatePort(port));
+                if server.is_default {
+                    if *has_default {
+                        error!("More than one default server for {}", address);
+                        critical.push(ValidatorError::DuplicateDefaultServer(address.clone()));
+                    }
+                    *has_default = true;
+                }
+
+                for name in &server.server_name {
+                    if !names.insert(name.to_lowercase()) {
+                        warn!("Duplicate server name '{}' on {}, the first server wins", name, address);
+                    }
                 }
             }
 
             // Validate server-level client_max_body_size (warning)
             if let Some(size) = &server.client_max_body_size {
                 if let Err(e) = Self::validate_body_size(size) {
-                    warn!("Invalid client_max_body_size '{}' in server {}: {}", size, server.host, e);
+                    warn!("Invalid client_max_body_size '{}' in server {}: {}", size, label, e);
                 }
             }
 
@@ -116,18 +229,18 @@ impl ConfigValidator {
             for route in &server.routes {
                 // Validate path and duplicates (warning)
                 if route.path.is_empty() || !route.path.starts_with('/') {
-                    warn!("Invalid route path '{}' in server {}", route.path, server.host);
+                    warn!("Invalid route path '{}' in server {}", route.path, label);
                     continue;
                 }
 
                 if !used_routes.insert(route.path.clone()) {
-                    warn!("Duplicate route '{}' in server {}", route.path, server.host);
+                    warn!("Duplicate route '{}' in server {}", route.path, label);
                     continue;
                 }
 
                 // Validate methods (warning)
                 if route.methods.is_empty() {
-                    warn!("No methods specified for route '{}' in server {}", route.path, server.host);
+                    warn!("No methods specified for route '{}' in server {}", route.path, label);
                 }
 
                 for method in &route.methods {
@@ -143,10 +256,22 @@ impl ConfigValidator {
                     }
                 }
 
-                // Validate redirect (warning)
+                if route.spa_fallback && route.index.is_none() {
+                    warn!("spa_fallback has no effect without an index in route '{}'", route.path);
+                }
+
+                // Validate redirect: codes outside 100-599 are critical, others
+                // that do not redirect are only warned about
                 if let Some(redirect) = &route.redirect {
-                    if !(300..=308).contains(&redirect.code) {
-                        warn!("Invalid redirect code {} for route '{}'", redirect.code, route.path);
+                    match StatusCode::from_u16(redirect.code) {
+                        None => {
+                            error!("Invalid redirect code {} for route '{}'", redirect.code, route.path);
+                            critical.push(ValidatorError::InvalidRedirectCode(redirect.code));
+                        }
+                        Some(status) if !status.is_redirect() => {
+                            warn!("Invalid redirect code {} for route '{}'", redirect.code, route.path);
+                        }
+                        Some(_) => {}
                     }
                 }
 
@@ -159,12 +284,11 @@ impl ConfigValidator {
             }
         }
 
-        if has_critical_error {
-            error!("Critical configuration errors found");
-            return Err(ValidatorError::NoPortsSpecified); // Using this as a generic critical error
+        if critical.is_empty() {
+            return Ok(());
         }
-
-        Ok(())
+        error!("{} critical configuration error(s) found", critical.len());
+        Err(critical.remove(0))
     }
 
     fn validate_path(path: &str) -> bool {
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Size of the pieces a streamed body is read in
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Most bytes a single sendfile(2) call transfers on Linux
+#[cfg(target_os = "linux")]
+const MAX_SENDFILE_CHUNK: u64 = 0x7fff_f000;
+
+/// Producer of body chunks, used for content generated while it is sent
+pub type ChunkIter = Box<dyn Iterator<Item = io::Result<Vec<u8>>> + Send + Sync>;
+
+/// The body of a response.
+///
+/// Only `Bytes` is held in memory. A `File` is handed to sendfile(2) on Linux
+/// and read from disk piece by piece elsewhere, and `Reader`/`Chunks` bodies
+/// have no known length, so they are sent with `Transfer-Encoding: chunked`.
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    /// `remaining` bytes of the file starting at `offset`
+    File { file: File, offset: u64, remaining: u64 },
+    /// Output of unknown length, such as a CGI script's stdout
+    Reader(Box<dyn Read + Send + Sync>),
+    Chunks(ChunkIter),
+}
+
+impl Body {
+    pub fn file(file: File) -> io::Result<Self> {
+        let remaining = file.metadata()?.len();
+        Ok(Body::File {
+            file,
+            offset: 0,
+            remaining,
+        })
+    }
+
+    pub fn reader(reader: impl Read + Send + Sync + 'static) -> Self {
+        Body::Reader(Box::new(reader))
+    }
+
+    pub fn chunks(chunks: impl Iterator<Item = io::Result<Vec<u8>>> + Send + Sync + 'static) -> Self {
+        Body::Chunks(Box::new(chunks))
+    }
+
+    /// Returns the length of the body if it is known before it is sent.
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { remaining, .. } => Some(*remaining),
+            Body::Reader(_) | Body::Chunks(_) => None,
+        }
+    }
+
+    /// Returns the next piece of the body, or `None` once it is exhausted.
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Body::Empty => Ok(None),
+            Body::Bytes(bytes) => {
+                let bytes = std::mem::take(bytes);
+                *self = Body::Empty;
+                Ok(Some(bytes))
+            }
+            Body::File {
+                file,
+                offset,
+                remaining,
+            } => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+
+                let mut chunk = vec![0; (*remaining).min(STREAM_CHUNK_SIZE as u64) as usize];
+                let n = file.read_at(&mut chunk, *offset)?;
+                if n == 0 {
+                    return Err(truncated_file());
+                }
+                chunk.truncate(n);
+                *offset += n as u64;
+                *remaining -= n as u64;
+                Ok(Some(chunk))
+            }
+            Body::Reader(reader) => {
+                let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+                loop {
+                    match reader.read(&mut chunk) {
+                        Ok(0) => return Ok(None),
+                        Ok(n) => {
+                            chunk.truncate(n);
+                            return Ok(Some(chunk));
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Body::Chunks(chunks) => chunks.next().transpose(),
+        }
+    }
+}
+
+fn truncated_file() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "File shorter than its announced length",
+    )
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::File { remaining, .. } => write!(f, "File({} bytes)", remaining),
+            Body::Reader(_) => write!(f, "Reader"),
+            Body::Chunks(_) => write!(f, "Chunks"),
+        }
+    }
+}
+
+/// A body on its way to the socket, framed according to how it was announced.
+///
+/// Bodies of unknown length are wrapped in chunked framing and terminated by
+/// the zero-size last chunk; others are passed through as they are.
+#[derive(Debug)]
+pub struct BodyWriter {
+    body: Body,
+    chunked: bool,
+    finished: bool,
+}
+
+impl BodyWriter {
+    pub fn new(body: Body) -> Self {
+        BodyWriter {
+            chunked: body.content_length().is_none(),
+            body,
+            finished: false,
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.body, Body::File { .. })
+    }
+
+    /// Returns the file descriptor, offset and length of the next piece of a
+    /// file body, for callers that read the file themselves and report what
+    /// they read with `advance_file`. `None` once the file is complete or if
+    /// the body is not a file.
+    pub fn next_file_read(&self) -> Option<(RawFd, u64, usize)> {
+        match &self.body {
+            Body::File {
+                file,
+                offset,
+                remaining,
+            } if *remaining > 0 => Some((
+                file.as_raw_fd(),
+                *offset,
+                (*remaining).min(STREAM_CHUNK_SIZE as u64) as usize,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Records that `n` bytes of a file body were read, see `next_file_read`.
+    pub fn advance_file(&mut self, n: usize) {
+        if let Body::File {
+            offset, remaining, ..
+        } = &mut self.body
+        {
+            *offset += n as u64;
+            *remaining -= (n as u64).min(*remaining);
+        }
+    }
+
+    /// Sends the next part of a file body with sendfile(2), so the data goes from
+    /// the page cache to `socket` without being copied through userspace.
+    ///
+    /// Returns `None` if the body is not a file, otherwise the number of bytes
+    /// sent, `0` meaning the whole file has been sent. The file offset only
+    /// advances by what the socket accepted, so a partial write resumes where
+    /// it stopped.
+    #[cfg(target_os = "linux")]
+    pub fn send_file(&mut self, socket: RawFd) -> Option<io::Result<usize>> {
+        let Body::File {
+            file,
+            offset,
+            remaining,
+        } = &mut self.body
+        else {
+            return None;
+        };
+
+        if *remaining == 0 {
+            self.finished = true;
+            return Some(Ok(0));
+        }
+
+        let mut file_offset = *offset as libc::off_t;
+        let count = (*remaining).min(MAX_SENDFILE_CHUNK) as usize;
+        let sent = unsafe { libc::sendfile(socket, file.as_raw_fd(), &mut file_offset, count) };
+        if sent < 0 {
+            return Some(Err(io::Error::last_os_error()));
+        }
+        if sent == 0 {
+            return Some(Err(truncated_file()));
+        }
+
+        *offset += sent as u64;
+        *remaining -= sent as u64;
+        Some(Ok(sent as usize))
+    }
+
+    /// Returns the next bytes to put on the wire, or `None` once the body is complete.
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        loop {
+            match self.body.next_chunk()? {
+                // An empty chunk would read as the end of a chunked body
+                Some(chunk) if chunk.is_empty() => continue,
+                Some(chunk) if self.chunked => {
+                    let mut frame = format!("{:X}\r\n", chunk.len()).into_bytes();
+                    frame.extend_from_slice(&chunk);
+                    frame.extend_from_slice(b"\r\n");
+                    return Ok(Some(frame));
+                }
+                Some(chunk) => return Ok(Some(chunk)),
+                None => {
+                    self.finished = true;
+                    return Ok(self.chunked.then(|| b"0\r\n\r\n".to_vec()));
+                }
+            }
+        }
+    }
+}
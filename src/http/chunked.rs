@@ -0,0 +1,364 @@
+use std::io;
+
+use crate::http::{Headers, RequestError, StatusCode};
+
+/// Longest chunk-size line (size plus extensions) accepted before giving up
+const MAX_CHUNK_LINE: usize = 4096;
+/// Largest trailer section accepted after the last chunk
+const MAX_TRAILER_SIZE: usize = 8192;
+
+/// Where the decoder is within a chunked body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    /// Waiting for a chunk-size line, optionally with chunk extensions
+    Size,
+    /// Inside chunk data, `remaining` bytes still to come
+    Data { remaining: usize },
+    /// Chunk data complete, waiting for its terminating CRLF
+    DataEnd,
+    /// Last chunk received, reading trailer fields until the empty line
+    Trailer,
+    /// The whole body has been decoded
+    Done,
+}
+
+/// Streaming decoder for `Transfer-Encoding: chunked` bodies (RFC 9112, section 7.1).
+///
+/// Input can be fed in arbitrary pieces: `decode` consumes what it can and reports
+/// how many bytes it used, so the caller only has to keep the unconsumed tail.
+/// Chunk extensions are validated and ignored, trailer fields are collected.
+#[derive(Debug, Clone)]
+pub struct ChunkedDecoder {
+    state: ChunkState,
+    body: Vec<u8>,
+    trailers: Headers,
+    trailer_size: usize,
+    max_size: Option<usize>,
+}
+
+impl ChunkedDecoder {
+    pub fn new(max_size: Option<usize>) -> Self {
+        ChunkedDecoder {
+            state: ChunkState::Size,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            trailer_size: 0,
+            max_size,
+        }
+    }
+
+    pub fn state(&self) -> ChunkState {
+        self.state
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    /// Decodes as much of `input` as possible and returns the number of bytes consumed.
+    ///
+    /// Fails with a 400 `RequestError` on malformed framing and with a 413 once the
+    /// decoded body grows past `max_size`.
+    pub fn decode(&mut self, input: &[u8]) -> io::Result<usize> {
+        let mut pos = 0;
+
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line_end = match find_crlf(&input[pos..]) {
+                        Some(end) => pos + end,
+                        None => {
+                            if input.len() - pos > MAX_CHUNK_LINE {
+                                return Err(malformed("Chunk size line too long"));
+                            }
+                            return Ok(pos);
+                        }
+                    };
+
+                    let size = parse_chunk_line(&input[pos..line_end])?;
+                    pos = line_end + 2;
+
+                    if size == 0 {
+                        self.state = ChunkState::Trailer;
+                        continue;
+                    }
+
+                    if let Some(max_size) = self.max_size {
+                        if self.body.len().saturating_add(size) > max_size {
+                            return Err(RequestError::new(
+                                StatusCode::ContentTooLarge,
+                                "Chunked body exceeds client_max_body_size",
+                            )
+                            .into());
+                        }
+                    }
+                    self.state = ChunkState::Data { remaining: size };
+                }
+                ChunkState::Data { remaining } => {
+                    let available = (input.len() - pos).min(remaining);
+                    self.body.extend_from_slice(&input[pos..pos + available]);
+                    pos += available;
+
+                    if available < remaining {
+                        self.state = ChunkState::Data {
+                            remaining: remaining - available,
+                        };
+                        return Ok(pos);
+                    }
+                    self.state = ChunkState::DataEnd;
+                }
+                ChunkState::DataEnd => {
+                    if input.len() - pos < 2 {
+                        return Ok(pos);
+                    }
+                    if &input[pos..pos + 2] != b"\r\n" {
+                        return Err(malformed("Missing CRLF after chunk data"));
+                    }
+                    pos += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailer => {
+                    let line_end = match find_crlf(&input[pos..]) {
+                        Some(end) => pos + end,
+                        None => {
+                            if self.trailer_size + (input.len() - pos) > MAX_TRAILER_SIZE {
+                                return Err(malformed("Trailer section too large"));
+                            }
+                            return Ok(pos);
+                        }
+                    };
+
+                    let line = &input[pos..line_end];
+                    pos = line_end + 2;
+
+                    // An empty line ends the trailer section and the body
+                    if line.is_empty() {
+                        self.state = ChunkState::Done;
+                        return Ok(pos);
+                    }
+
+                    self.trailer_size += line.len() + 2;
+                    if self.trailer_size > MAX_TRAILER_SIZE {
+                        return Err(malformed("Trailer section too large"));
+                    }
+                    self.add_trailer(line)?;
+                }
+                ChunkState::Done => return Ok(pos),
+            }
+        }
+    }
+
+    /// Returns the decoded body and the trailer fields.
+    pub fn into_parts(self) -> (Vec<u8>, Headers) {
+        (self.body, self.trailers)
+    }
+
+    fn add_trailer(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = std::str::from_utf8(line).map_err(|_| malformed("Invalid trailer field"))?;
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| malformed("Invalid trailer field"))?;
+
+        if name.is_empty() || !name.bytes().all(is_token_char) {
+            return Err(malformed("Invalid trailer field name"));
+        }
+
+        self.trailers.append(name, value.trim());
+        Ok(())
+    }
+}
+
+/// Parses `chunk-size [ chunk-ext ]` and returns the chunk size
+fn parse_chunk_line(line: &[u8]) -> io::Result<usize> {
+    let line = std::str::from_utf8(line).map_err(|_| malformed("Invalid chunk size"))?;
+    let (size, extensions) = match line.split_once(';') {
+        Some((size, extensions)) => (size, Some(extensions)),
+        None => (line, None),
+    };
+
+    let size = size.trim_end_matches([' ', '\t']);
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(malformed("Invalid chunk size"));
+    }
+    let size = usize::from_str_radix(size, 16).map_err(|_| malformed("Chunk size too large"))?;
+
+    if let Some(extensions) = extensions {
+        for extension in extensions.split(';') {
+            validate_chunk_extension(extension)?;
+        }
+    }
+
+    Ok(size)
+}
+
+/// Validates `chunk-ext-name [ "=" chunk-ext-val ]`, the value being a token or quoted-string
+fn validate_chunk_extension(extension: &str) -> io::Result<()> {
+    let (name, value) = match extension.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (extension.trim(), None),
+    };
+
+    if name.is_empty() || !name.bytes().all(is_token_char) {
+        return Err(malformed("Invalid chunk extension"));
+    }
+
+    if let Some(value) = value {
+        let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+        if !quoted && (value.is_empty() || !value.bytes().all(is_token_char)) {
+            return Err(malformed("Invalid chunk extension"));
+        }
+    }
+    Ok(())
+}
+
+/// tchar as defined in RFC 9110, section 5.6.2
+pub fn is_token_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Position of the first CRLF in `buffer`
+pub fn find_crlf(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|window| window == b"\r\n")
+}
+
+fn malformed(reason: &'static str) -> io::Error {
+    RequestError::new(StatusCode::BadRequest, reason).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `input` in one piece and returns the status of the failure, if any.
+    fn rejection(input: &[u8], max_size: Option<usize>) -> Option<StatusCode> {
+        match ChunkedDecoder::new(max_size).decode(input) {
+            Ok(_) => None,
+            Err(e) => RequestError::status_of(&e),
+        }
+    }
+
+    #[test]
+    fn decodes_in_one_piece() {
+        let input = b"5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\nnext request";
+        let mut decoder = ChunkedDecoder::new(None);
+
+        assert_eq!(decoder.decode(input).unwrap(), input.len() - b"next request".len());
+        assert!(decoder.is_done());
+        assert_eq!(decoder.into_parts().0, b"hello, world");
+    }
+
+    #[test]
+    fn decodes_one_byte_at_a_time() {
+        let input = b"5;name=value\r\nhello\r\na\r\n, world!!!\r\n0\r\nExpires: never\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(None);
+
+        // The caller keeps what the decoder did not consume and adds the next byte
+        let mut pending = Vec::new();
+        for &byte in input {
+            assert!(!decoder.is_done());
+            pending.push(byte);
+            let consumed = decoder.decode(&pending).unwrap();
+            pending.drain(..consumed);
+        }
+
+        assert!(decoder.is_done());
+        assert!(pending.is_empty());
+        let (body, trailers) = decoder.into_parts();
+        assert_eq!(body, b"hello, world!!!");
+        assert_eq!(trailers.get("Expires").map(String::as_str), Some("never"));
+    }
+
+    #[test]
+    fn accepts_chunk_extensions() {
+        let input = b"3;flag;name=token;quoted=\"a value\" ; spaced = x\r\nabc\r\n0;last\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(None);
+
+        assert_eq!(decoder.decode(input).unwrap(), input.len());
+        assert_eq!(decoder.into_parts().0, b"abc");
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_extensions() {
+        assert_eq!(rejection(b"3;\r\nabc\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"3;na me\r\nabc\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"3;name=\r\nabc\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"3;name=a@b\r\nabc\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"3;name=\"open\r\nabc\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn rejects_invalid_chunk_sizes() {
+        assert_eq!(rejection(b"\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"-3\r\nabc\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"0x3\r\nabc\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"fffffffffffffffffffff\r\n", None), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn collects_trailers() {
+        let input = b"3\r\nabc\r\n0\r\nExpires: never\r\nX-Checksum:  abc \r\nX-Checksum: def\r\n\r\n";
+        let mut decoder = ChunkedDecoder::new(None);
+
+        assert_eq!(decoder.decode(input).unwrap(), input.len());
+        let (body, trailers) = decoder.into_parts();
+        assert_eq!(body, b"abc");
+        assert_eq!(trailers.get("Expires").map(String::as_str), Some("never"));
+        assert_eq!(trailers.get_all("X-Checksum").collect::<Vec<_>>(), ["abc", "def"]);
+    }
+
+    #[test]
+    fn rejects_malformed_trailers() {
+        assert_eq!(rejection(b"0\r\nNo colon\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"0\r\nBad name: x\r\n\r\n", None), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn limits_the_chunk_size_line() {
+        let mut line = b"3;name=".to_vec();
+        line.resize(MAX_CHUNK_LINE + 1, b'x');
+        // Refused before its CRLF arrives
+        assert_eq!(rejection(&line, None), Some(StatusCode::BadRequest));
+
+        // A line just within the limit is still waited for
+        line.truncate(MAX_CHUNK_LINE);
+        assert_eq!(ChunkedDecoder::new(None).decode(&line).unwrap(), 0);
+    }
+
+    #[test]
+    fn limits_the_trailer_section() {
+        let field = format!("X-Padding: {}\r\n", "x".repeat(1000));
+        let mut input = b"0\r\n".to_vec();
+        for _ in 0..MAX_TRAILER_SIZE / field.len() + 1 {
+            input.extend_from_slice(field.as_bytes());
+        }
+        input.extend_from_slice(b"\r\n");
+        assert_eq!(rejection(&input, None), Some(StatusCode::BadRequest));
+
+        // Also refused while the last field is incomplete
+        let mut input = b"0\r\n".to_vec();
+        input.resize(MAX_TRAILER_SIZE + 4, b'x');
+        assert_eq!(rejection(&input, None), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn limits_the_body_size() {
+        assert_eq!(rejection(b"3\r\nabc\r\n0\r\n\r\n", Some(3)), None);
+        assert_eq!(
+            rejection(b"3\r\nabc\r\n1\r\nd\r\n0\r\n\r\n", Some(3)),
+            Some(StatusCode::ContentTooLarge)
+        );
+        // Refused on the size line, before any of the chunk arrives
+        assert_eq!(rejection(b"ff\r\n", Some(3)), Some(StatusCode::ContentTooLarge));
+    }
+
+    #[test]
+    fn requires_crlf_after_chunk_data() {
+        assert_eq!(rejection(b"3\r\nabcd\r\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+        assert_eq!(rejection(b"3\r\nabc\n0\r\n\r\n", None), Some(StatusCode::BadRequest));
+
+        // Waits for both bytes before deciding
+        let mut decoder = ChunkedDecoder::new(None);
+        assert_eq!(decoder.decode(b"3\r\nabc\r").unwrap(), 6);
+        assert_eq!(decoder.state(), ChunkState::DataEnd);
+    }
+}
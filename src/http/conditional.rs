@@ -0,0 +1,285 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::{Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+use crate::http::methods::Method;
+use crate::http::{Request, Response, StatusCode};
+
+/// Format of `Last-Modified` and the other HTTP dates kang sends
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+/// Obsolete date formats recipients must still accept: RFC 850 and asctime
+const OBSOLETE_HTTP_DATES: [&str; 2] = ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"];
+
+/// The validators of a representation, sent as `ETag` and `Last-Modified`
+/// and compared with the conditional headers of requests.
+#[derive(Debug, Clone)]
+pub struct Validators {
+    /// A strong entity tag, quotes included
+    pub etag: String,
+    /// Truncated to seconds, the precision of HTTP dates
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    /// Validators of a file: the tag is made of its modification time and
+    /// size, which change whenever it is written.
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let modified = metadata.modified().ok();
+        let nanos = modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_nanos());
+
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", nanos, metadata.len()),
+            last_modified: modified.map(to_seconds),
+        }
+    }
+
+    /// Validators of generated content, such as a directory listing, whose
+    /// tag is a hash of the content itself.
+    pub fn from_content(content: &[u8], modified: Option<SystemTime>) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+
+        Validators {
+            etag: format!("\"{:x}\"", hasher.finish()),
+            last_modified: modified.map(to_seconds),
+        }
+    }
+
+    /// Adds `ETag` and `Last-Modified` to a response carrying the representation.
+    pub fn apply(&self, response: &mut Response) {
+        response.set_header("ETag", &self.etag);
+        if let Some(last_modified) = self.last_modified {
+            response.set_header("Last-Modified", &last_modified.format(HTTP_DATE).to_string());
+        }
+    }
+
+    /// The 304 sent instead of the representation, with its validators.
+    pub fn not_modified(&self) -> Response {
+        let mut response = Response::new(StatusCode::NotModified);
+        self.apply(&mut response);
+        response
+    }
+}
+
+/// Evaluates the conditional headers of `request` against the current
+/// representation of the target, `None` if it has none, in the order of
+/// RFC 9110 section 13.2.2.
+///
+/// Fails with `NotModified` when a GET or HEAD can be answered with a 304,
+/// and with `PreconditionFailed` when the request must not be applied.
+pub fn evaluate(request: &Request, current: Option<&Validators>) -> Result<(), StatusCode> {
+    let headers = request.headers();
+    let safe = matches!(request.method(), Method::GET | Method::HEAD);
+
+    // If-Match, or else If-Unmodified-Since, guards against lost updates
+    if let Some(if_match) = headers.get_joined("If-Match") {
+        let matched = match current {
+            Some(current) => matches_any(&if_match, &current.etag, true),
+            None => false,
+        };
+        if !matched {
+            return Err(StatusCode::PreconditionFailed);
+        }
+    } else if let Some(since) = headers.get("If-Unmodified-Since").and_then(|date| parse_http_date(date)) {
+        if current.and_then(|current| current.last_modified).is_some_and(|modified| modified > since) {
+            return Err(StatusCode::PreconditionFailed);
+        }
+    }
+
+    // If-None-Match, or else If-Modified-Since for GET and HEAD, avoids
+    // sending what the client already has
+    if let Some(if_none_match) = headers.get_joined("If-None-Match") {
+        let matched = match current {
+            Some(current) => matches_any(&if_none_match, &current.etag, false),
+            None => false,
+        };
+        if matched {
+            return Err(if safe { StatusCode::NotModified } else { StatusCode::PreconditionFailed });
+        }
+    } else if let Some(since) = headers.get("If-Modified-Since").and_then(|date| parse_http_date(date)) {
+        if safe && current.and_then(|current| current.last_modified).is_some_and(|modified| modified <= since) {
+            return Err(StatusCode::NotModified);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns true if the list of entity tags holds `*` or `etag`. The strong
+/// comparison of If-Match never matches weak tags; the weak one of
+/// If-None-Match ignores the `W/` prefix.
+fn matches_any(list: &str, etag: &str, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+    entity_tags(list).any(|(weak, tag)| tag == etag && !(strong && weak))
+}
+
+/// Splits a list of entity tags into their weakness and quoted tag. Tags are
+/// delimited by quotes rather than commas, which they may contain.
+fn entity_tags(list: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            return None;
+        }
+
+        let weak = rest.starts_with("W/");
+        let tag = rest.strip_prefix("W/").unwrap_or(rest);
+        let end = match tag.strip_prefix('"').and_then(|inner| inner.find('"')) {
+            Some(end) => end + 2,
+            // Not a quoted tag: skip to the next element
+            None => tag.find(',').unwrap_or(tag.len()),
+        };
+        let (tag, remainder) = tag.split_at(end);
+        rest = remainder;
+        Some((weak, tag))
+    })
+}
+
+/// Parses an HTTP date in the preferred format or either obsolete one.
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = date.trim();
+    std::iter::once(HTTP_DATE)
+        .chain(OBSOLETE_HTTP_DATES)
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|date| date.and_utc())
+}
+
+fn to_seconds(time: SystemTime) -> DateTime<Utc> {
+    let time = DateTime::<Utc>::from(time);
+    DateTime::from_timestamp(time.timestamp(), 0).unwrap_or(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Headers;
+
+    /// The representation the requests are evaluated against
+    fn current() -> Validators {
+        Validators {
+            etag: "\"abc\"".to_string(),
+            last_modified: parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        }
+    }
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(method, "/file", "HTTP/1.1");
+        let mut fields = Headers::new();
+        for (name, value) in headers {
+            fields.append(name, value);
+        }
+        request.set_headers(fields);
+        request
+    }
+
+    fn get(headers: &[(&str, &str)]) -> Result<(), StatusCode> {
+        evaluate(&request(Method::GET, headers), Some(&current()))
+    }
+
+    fn put(headers: &[(&str, &str)]) -> Result<(), StatusCode> {
+        evaluate(&request(Method::PUT, headers), Some(&current()))
+    }
+
+    const EARLIER: &str = "Sat, 05 Nov 1994 08:49:37 GMT";
+    const LATER: &str = "Mon, 07 Nov 1994 08:49:37 GMT";
+
+    #[test]
+    fn unconditional_requests_pass() {
+        assert_eq!(get(&[]), Ok(()));
+        assert_eq!(put(&[]), Ok(()));
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        assert_eq!(put(&[("If-Match", "\"abc\""), ("If-Unmodified-Since", EARLIER)]), Ok(()));
+        assert_eq!(
+            put(&[("If-Match", "\"other\""), ("If-Unmodified-Since", LATER)]),
+            Err(StatusCode::PreconditionFailed)
+        );
+        // Without If-Match, If-Unmodified-Since decides
+        assert_eq!(put(&[("If-Unmodified-Since", LATER)]), Ok(()));
+        assert_eq!(put(&[("If-Unmodified-Since", EARLIER)]), Err(StatusCode::PreconditionFailed));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        assert_eq!(get(&[("If-None-Match", "\"other\""), ("If-Modified-Since", LATER)]), Ok(()));
+        assert_eq!(
+            get(&[("If-None-Match", "\"abc\""), ("If-Modified-Since", EARLIER)]),
+            Err(StatusCode::NotModified)
+        );
+        // Without If-None-Match, If-Modified-Since decides, for GET and HEAD only
+        assert_eq!(get(&[("If-Modified-Since", LATER)]), Err(StatusCode::NotModified));
+        assert_eq!(get(&[("If-Modified-Since", EARLIER)]), Ok(()));
+        assert_eq!(put(&[("If-Modified-Since", LATER)]), Ok(()));
+    }
+
+    #[test]
+    fn if_none_match_fails_unsafe_methods() {
+        assert_eq!(put(&[("If-None-Match", "\"abc\"")]), Err(StatusCode::PreconditionFailed));
+    }
+
+    #[test]
+    fn star_matches_any_current_representation() {
+        assert_eq!(put(&[("If-Match", "*")]), Ok(()));
+        assert_eq!(
+            evaluate(&request(Method::PUT, &[("If-Match", "*")]), None),
+            Err(StatusCode::PreconditionFailed)
+        );
+
+        assert_eq!(put(&[("If-None-Match", "*")]), Err(StatusCode::PreconditionFailed));
+        assert_eq!(evaluate(&request(Method::PUT, &[("If-None-Match", "*")]), None), Ok(()));
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        assert_eq!(put(&[("If-Match", "W/\"abc\"")]), Err(StatusCode::PreconditionFailed));
+        assert_eq!(put(&[("If-Match", "W/\"abc\", \"abc\"")]), Ok(()));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert_eq!(get(&[("If-None-Match", "W/\"abc\"")]), Err(StatusCode::NotModified));
+        assert_eq!(get(&[("If-None-Match", "W/\"other\"")]), Ok(()));
+    }
+
+    #[test]
+    fn splits_lists_on_quotes_rather_than_commas() {
+        let tags: Vec<_> = entity_tags("\"a,b\", W/\"c\" ,\"\",, \"d\"").collect();
+        assert_eq!(tags, [(false, "\"a,b\""), (true, "\"c\""), (false, "\"\""), (false, "\"d\"")]);
+
+        assert!(matches_any("\"x\", \"a,b\"", "\"a,b\"", true));
+        assert!(!matches_any("\"a\", \"b\"", "\"a,b\"", true));
+        // Unquoted elements are skipped without losing the rest of the list
+        assert!(matches_any("abc, \"abc\"", "\"abc\"", true));
+    }
+
+    #[test]
+    fn combines_repeated_fields() {
+        assert_eq!(put(&[("If-Match", "\"x\""), ("If-Match", "\"abc\"")]), Ok(()));
+    }
+
+    #[test]
+    fn ignores_unparseable_dates() {
+        assert_eq!(get(&[("If-Modified-Since", "yesterday")]), Ok(()));
+        assert_eq!(put(&[("If-Unmodified-Since", "1994-11-06T08:49:37Z")]), Ok(()));
+    }
+
+    #[test]
+    fn parses_every_http_date_format() {
+        let expected = current().last_modified;
+        assert!(expected.is_some());
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date(" Sun, 06 Nov 1994 08:49:37 GMT "), expected);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994"), None);
+    }
+}
@@ -53,41 +53,33 @@ impl Cookie {
     pub fn set_http_only(&mut self, http_only: bool) {
         self.http_only = Some(http_only);
     }
+}
 
-    pub fn to_string(&self) -> String {
-        let mut cookie_str = format!("{}={}", self.name, self.value);
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
 
         if let Some(expires) = &self.expires {
-            cookie_str.push_str(&format!("; expires={}", expires));
+            write!(f, "; expires={}", expires)?;
         }
 
         if let Some(path) = &self.path {
-            cookie_str.push_str(&format!("; path={}", path));
+            write!(f, "; path={}", path)?;
         }
 
         if let Some(domain) = &self.domain {
-            cookie_str.push_str(&format!("; domain={}", domain));
+            write!(f, "; domain={}", domain)?;
         }
 
-        if let Some(secure) = self.secure {
-            if secure {
-                cookie_str.push_str("; secure");
-            }
+        if let Some(true) = self.secure {
+            write!(f, "; secure")?;
         }
 
-        if let Some(http_only) = self.http_only {
-            if http_only {
-                cookie_str.push_str("; HttpOnly");
-            }
+        if let Some(true) = self.http_only {
+            write!(f, "; HttpOnly")?;
         }
 
-        cookie_str
-    }
-}
-
-impl fmt::Display for Cookie {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        Ok(())
     }
 }
 
@@ -0,0 +1,35 @@
+use std::io;
+
+use thiserror::Error;
+
+use super::status::StatusCode;
+
+/// A request that cannot be served, together with the status the client gets back.
+///
+/// It travels inside an `io::Error` of kind `InvalidData` so it fits the
+/// `io::Result` signatures used by the listeners.
+#[derive(Debug, Error)]
+#[error("{reason}")]
+pub struct RequestError {
+    pub status: StatusCode,
+    pub reason: &'static str,
+}
+
+impl RequestError {
+    pub fn new(status: StatusCode, reason: &'static str) -> Self {
+        RequestError { status, reason }
+    }
+
+    /// Returns the status carried by an `io::Error` built from a `RequestError`.
+    pub fn status_of(err: &io::Error) -> Option<StatusCode> {
+        err.get_ref()?
+            .downcast_ref::<RequestError>()
+            .map(|e| e.status)
+    }
+}
+
+impl From<RequestError> for io::Error {
+    fn from(err: RequestError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
@@ -1,19 +1,33 @@
-use std::fs;
+use std::fs::{self, File};
 use std::path::PathBuf;
 use serde_json::json;
 
 use crate::debug;
 use crate::http::status::StatusCode;
-use crate::http::Response;
+use crate::http::conditional::{self, Validators};
+use crate::http::{Body, Request, Response};
 use crate::config::config::Config;
 
 pub struct FileServer;
 
 impl FileServer {
-    pub fn serve_file(path: PathBuf) -> Response {
-        match fs::read(&path) {
-            Ok(content) => {
+    /// Serves a file, streaming it from disk as the client reads it. The
+    /// conditional headers of `request` may turn it into a 304 or a 412.
+    pub fn serve_file(path: PathBuf, request: &Request) -> Result<Response, StatusCode> {
+        let file = File::open(&path).map_err(|_| StatusCode::InternalServerError)?;
+        let validators = file
+            .metadata()
+            .map(|metadata| Validators::from_metadata(&metadata))
+            .map_err(|_| StatusCode::InternalServerError)?;
+        match conditional::evaluate(request, Some(&validators)) {
+            Err(StatusCode::NotModified) => return Ok(validators.not_modified()),
+            result => result?,
+        }
+
+        match Body::file(file) {
+            Ok(body) => {
                 let mut response = Response::new(StatusCode::Ok);
+                validators.apply(&mut response);
 
                 // Set content type based on extension
                 if let Some(ext) = path.extension() {
@@ -36,14 +50,17 @@ impl FileServer {
                     response.set_header("Content-Type", content_type);
                 }
 
-                response.set_body(content);
-                response
+                response.set_body_stream(body);
+                Ok(response)
             }
-            Err(_) => Response::new(StatusCode::InternalServerError),
+            Err(_) => Err(StatusCode::InternalServerError),
         }
     }
 
-    pub fn serve_directory_listing(path: &PathBuf, request_path: &str, config: &Config) -> Response {
+    /// Lists a directory. Its validators come from the listing itself and the
+    /// modification time of the directory.
+    pub fn serve_directory_listing(path: &PathBuf, request: &Request, config: &Config) -> Result<Response, StatusCode> {
+        let request_path = request.path();
         match fs::read_dir(path) {
             Ok(entries) => {
                 let mut entries_vec: Vec<_> = entries
@@ -102,9 +119,21 @@ impl FileServer {
                         response.set_body(html.into_bytes());
                     }
                 };
-                response
+
+                let content = match response.body() {
+                    Body::Bytes(content) => content.as_slice(),
+                    _ => &[],
+                };
+                let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+                let validators = Validators::from_content(content, modified);
+                match conditional::evaluate(request, Some(&validators)) {
+                    Err(StatusCode::NotModified) => return Ok(validators.not_modified()),
+                    result => result?,
+                }
+                validators.apply(&mut response);
+                Ok(response)
             }
-            Err(_) => Response::new(StatusCode::InternalServerError),
+            Err(_) => Err(StatusCode::InternalServerError),
         }
     }
 }
@@ -1,29 +1,101 @@
-use std::collections::HashMap;
-
 use super::cookies::Cookie;
 
-#[derive(Debug, Clone)]
+/// Header fields in the order they were added, with the casing of their names
+/// kept. Names are compared case-insensitively, and a name may carry several
+/// values, each written on its own line.
+#[derive(Debug, Clone, Default)]
 pub struct Headers {
-    headers: HashMap<String, String>,
+    fields: Vec<(String, String)>,
 }
 
 impl Headers {
     pub fn new() -> Self {
-        Headers {
-            headers: HashMap::new(),
+        Headers { fields: Vec::new() }
+    }
+
+    /// Sets `name` to `value` alone, replacing every value it had. The field
+    /// keeps the position of the first value replaced.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        let Some(first) = self.position(name) else {
+            return self.append(name, value);
+        };
+
+        self.fields[first] = (name.to_string(), value.to_string());
+        let mut index = 0;
+        self.fields.retain(|(field, _)| {
+            let keep = index <= first || !field.eq_ignore_ascii_case(name);
+            index += 1;
+            keep
+        });
+    }
+
+    /// Adds a value for `name` after those it already has.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Removes every value of `name`, returning the first.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let first = self.position(name)?;
+        let value = self.fields.remove(first).1;
+        self.fields.retain(|(field, _)| !field.eq_ignore_ascii_case(name));
+        Some(value)
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.position(name).map(|index| &self.fields[index].1)
+    }
+
+    /// Every value of `name`, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Every value of `name` combined into one, as RFC 9110 allows for fields
+    /// holding a list: separated by commas, or by semicolons for `Cookie`.
+    /// `Set-Cookie` values cannot be combined, only the first is returned.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let separator = match name.to_ascii_lowercase().as_str() {
+            "set-cookie" => return self.get(name).cloned(),
+            "cookie" => "; ",
+            _ => ", ",
+        };
+
+        let values: Vec<&str> = self.get_all(name).map(String::as_str).collect();
+        (!values.is_empty()).then(|| values.join(separator))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Sets every field of `other`, whose values replace those of the same name.
+    /// `Set-Cookie` values are added instead, as each sets a different cookie.
+    pub fn merge(&mut self, other: Headers) {
+        for (name, _) in &other.fields {
+            if !name.eq_ignore_ascii_case("set-cookie") {
+                self.fields.retain(|(field, _)| !field.eq_ignore_ascii_case(name));
+            }
         }
+        self.fields.extend(other.fields);
     }
 
-    pub fn add(&mut self, key: &str, value: &str) {
-        self.headers.insert(key.to_lowercase(), value.to_string());
+    pub fn len(&self) -> usize {
+        self.fields.len()
     }
 
-    pub fn get(&self, key: &str) -> Option<&String> {
-        self.headers.get(&key.to_lowercase())
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
     }
 
-    pub fn contains(&self, key: &str) -> bool {
-        self.headers.contains_key(&key.to_lowercase())
+    fn position(&self, name: &str) -> Option<usize> {
+        self.fields
+            .iter()
+            .position(|(field, _)| field.eq_ignore_ascii_case(name))
     }
 
     pub fn parse(header_lines: &[&str]) -> Self {
@@ -35,7 +107,7 @@ impl Headers {
             if let Some(colon_idx) = line.find(':') {
                 let key = &line[0..colon_idx].trim();
                 let value = &line[(colon_idx + 1)..].trim();
-                headers.add(key, value);
+                headers.append(key, value);
             }
         }
         headers
@@ -78,12 +150,13 @@ impl Headers {
             .and_then(|length| length.parse::<u64>().ok())
     }
 
+    /// Every field, in the order they were added.
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
-        self.headers.iter()
+        self.fields.iter().map(|(name, value)| (name, value))
     }
 
     pub fn get_cookie(&self, name: &str) -> Option<Cookie> {
-        self.get("cookie").and_then(|cookie_header| {
+        self.get_joined("cookie").and_then(|cookie_header| {
             for cookie_str in cookie_header.split(';') {
                 let cookie_parts: Vec<&str> = cookie_str.trim().splitn(2, '=').collect();
                 if cookie_parts.len() == 2 && cookie_parts[0] == name {
@@ -101,4 +174,27 @@ impl Headers {
             None
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_replaces_fields_but_adds_cookies() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        headers.append("Set-Cookie", "KANGSESSID=1");
+
+        let mut other = Headers::new();
+        other.append("content-type", "text/plain");
+        other.append("Set-Cookie", "theme=dark");
+        other.append("set-cookie", "lang=en");
+        headers.merge(other);
+
+        assert_eq!(headers.get_all("Content-Type").collect::<Vec<_>>(), ["text/plain"]);
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["KANGSESSID=1", "theme=dark", "lang=en"]
+        );
+    }
+}
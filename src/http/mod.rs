@@ -1,4 +1,8 @@
+pub mod body;
+pub mod chunked;
+pub mod errors;
 pub mod headers;
+pub mod parser;
 pub mod request;
 pub mod response;
 pub mod upload;
@@ -6,11 +10,16 @@ pub mod status;
 pub mod files;
 pub mod methods;
 pub mod cookies;
+pub mod conditional;
 pub mod sessions;
 
+pub use body::{Body, BodyWriter};
+pub use chunked::{ChunkState, ChunkedDecoder};
+pub use errors::RequestError;
 pub use headers::Headers;
+pub use parser::{Framed, ParsePhase, RequestLimits, RequestParser};
 pub use request::Request;
 pub use response::Response;
 pub use upload::{UploadHandler, UploadedFile, MultipartFormData};
 pub use status::StatusCode;
-pub use sessions::{Session, SessionStore};
+pub use sessions::{Session, SessionStore, SharedSessionStore};
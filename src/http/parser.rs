@@ -0,0 +1,547 @@
+use std::io;
+
+use crate::http::chunked::{find_crlf, is_token_char};
+use crate::http::{ChunkState, ChunkedDecoder, Headers, RequestError, StatusCode};
+
+/// Limits enforced while a request is being received.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    /// Largest body accepted, after chunked decoding
+    pub max_body_size: Option<usize>,
+    /// Longest request line, longer ones are answered with 414
+    pub max_request_line: usize,
+    /// Largest header section after the request line, larger ones are answered with 431
+    pub max_header_size: usize,
+    /// Most header fields in a request, more are answered with 431
+    pub max_headers: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_body_size: None,
+            max_request_line: 8 * 1024,
+            max_header_size: 32 * 1024,
+            max_headers: 100,
+        }
+    }
+}
+
+/// How the length of a request body is determined, RFC 9112 section 6.
+enum BodyFraming {
+    Chunked,
+    Length(usize),
+}
+
+/// Where the parser is within the request currently being received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsePhase {
+    /// Waiting for the end of the request line
+    RequestLine,
+    /// Request line received, waiting for the blank line that ends the headers
+    Headers,
+    /// Headers received, waiting until the buffer holds `end` bytes
+    Body { end: usize },
+    /// Decoding a chunked body, including its trailer section
+    Chunked(ChunkState),
+}
+
+/// A complete request found at the front of the buffer.
+#[derive(Debug)]
+pub struct Framed {
+    /// Bytes taken by the request line and headers, including the blank line
+    pub head_length: usize,
+    /// Total bytes taken by the request, including any body framing
+    pub length: usize,
+    /// Decoded body and trailer fields, if the body was chunked
+    pub chunked: Option<(Vec<u8>, Headers)>,
+}
+
+/// Incremental framing of HTTP/1.1 requests.
+///
+/// The parser is fed the same growing buffer on every readiness event and
+/// remembers how far it got, so a request split across several reads is picked
+/// up where it left off instead of being scanned from the start again.
+#[derive(Debug, Clone)]
+pub struct RequestParser {
+    phase: ParsePhase,
+    cursor: usize,
+    /// End of the request line, where the header section starts
+    request_line_end: usize,
+    head_length: usize,
+    decoder: Option<ChunkedDecoder>,
+}
+
+impl Default for RequestParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestParser {
+    pub fn new() -> Self {
+        RequestParser {
+            phase: ParsePhase::RequestLine,
+            cursor: 0,
+            request_line_end: 0,
+            head_length: 0,
+            decoder: None,
+        }
+    }
+
+    pub fn phase(&self) -> ParsePhase {
+        match &self.decoder {
+            Some(decoder) => ParsePhase::Chunked(decoder.state()),
+            None => self.phase,
+        }
+    }
+
+    /// Returns true if no byte of the current request has been consumed yet.
+    pub fn is_idle(&self) -> bool {
+        self.phase == ParsePhase::RequestLine && self.cursor == 0
+    }
+
+    /// Resets the parser so it is ready for the next request on the connection.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Advances through `buffer`, which must start at the first byte of the request.
+    ///
+    /// Returns the framing of the request once it is complete, or `None` if more
+    /// data is needed. The parser is reset after a complete request.
+    ///
+    /// The request line and headers are validated as soon as they are complete,
+    /// failing with a `RequestError` carrying 400, 414, 431, 501 or 505.
+    pub fn advance(&mut self, buffer: &[u8], limits: &RequestLimits) -> io::Result<Option<Framed>> {
+        loop {
+            match self.phase {
+                ParsePhase::RequestLine => match buffer.get(self.cursor..).and_then(find_crlf) {
+                    Some(line_end) => {
+                        let line_end = self.cursor + line_end;
+                        if line_end > limits.max_request_line {
+                            return Err(reject(StatusCode::UriTooLong, "Request line too long"));
+                        }
+                        validate_request_line(&buffer[..line_end])?;
+                        self.phase = ParsePhase::Headers;
+                        self.cursor = line_end;
+                        self.request_line_end = line_end;
+                    }
+                    None => {
+                        if buffer.len() > limits.max_request_line {
+                            return Err(reject(StatusCode::UriTooLong, "Request line too long"));
+                        }
+                        self.cursor = buffer.len().saturating_sub(1);
+                        return Ok(None);
+                    }
+                },
+                ParsePhase::Headers => {
+                    // The request line's CRLF may be the first half of the terminator
+                    let start = self.cursor;
+                    let headers_end = match buffer[start..]
+                        .windows(4)
+                        .position(|window| window == b"\r\n\r\n")
+                    {
+                        Some(pos) => start + pos,
+                        None => {
+                            if buffer.len() - self.request_line_end > limits.max_header_size + 2 {
+                                return Err(reject(
+                                    StatusCode::RequestHeaderFieldsTooLarge,
+                                    "Header section too large",
+                                ));
+                            }
+                            self.cursor = buffer.len().saturating_sub(3).max(start);
+                            return Ok(None);
+                        }
+                    };
+                    if headers_end - self.request_line_end > limits.max_header_size {
+                        return Err(reject(
+                            StatusCode::RequestHeaderFieldsTooLarge,
+                            "Header section too large",
+                        ));
+                    }
+
+                    let body_start = headers_end + 4;
+                    let header_block = std::str::from_utf8(&buffer[..headers_end]).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Invalid UTF-8 in headers")
+                    })?;
+                    let mut lines = header_block.split("\r\n");
+                    let http_1_0 = lines.next().is_some_and(|line| line.ends_with("HTTP/1.0"));
+                    let headers = parse_header_fields(lines, http_1_0, limits)?;
+
+                    self.cursor = body_start;
+                    self.head_length = body_start;
+                    match body_framing(&headers, http_1_0)? {
+                        BodyFraming::Chunked => {
+                            self.decoder = Some(ChunkedDecoder::new(limits.max_body_size));
+                            self.phase = ParsePhase::Chunked(ChunkState::Size);
+                        }
+                        BodyFraming::Length(length) => {
+                            if limits.max_body_size.is_some_and(|max| length > max) {
+                                return Err(reject(
+                                    StatusCode::ContentTooLarge,
+                                    "Body exceeds client_max_body_size",
+                                ));
+                            }
+                            let end = body_start.checked_add(length).ok_or_else(|| {
+                                reject(StatusCode::ContentTooLarge, "Content-Length too large")
+                            })?;
+                            self.phase = ParsePhase::Body { end };
+                        }
+                    }
+                }
+                ParsePhase::Body { end } => {
+                    if buffer.len() < end {
+                        return Ok(None);
+                    }
+                    let framed = Framed {
+                        head_length: self.head_length,
+                        length: end,
+                        chunked: None,
+                    };
+                    self.reset();
+                    return Ok(Some(framed));
+                }
+                ParsePhase::Chunked(_) => {
+                    let decoder = match self.decoder.as_mut() {
+                        Some(decoder) => decoder,
+                        None => return Err(io::Error::other("Chunked phase without decoder")),
+                    };
+
+                    // Only the bytes not seen on previous events are fed to the decoder
+                    self.cursor += decoder.decode(&buffer[self.cursor..])?;
+                    if !decoder.is_done() {
+                        return Ok(None);
+                    }
+
+                    let framed = Framed {
+                        head_length: self.head_length,
+                        length: self.cursor,
+                        chunked: self.decoder.take().map(ChunkedDecoder::into_parts),
+                    };
+                    self.reset();
+                    return Ok(Some(framed));
+                }
+            }
+        }
+    }
+}
+
+fn reject(status: StatusCode, reason: &'static str) -> io::Error {
+    RequestError::new(status, reason).into()
+}
+
+/// Methods and field names are tokens: one or more `tchar`
+fn is_token(value: &[u8]) -> bool {
+    !value.is_empty() && value.iter().copied().all(is_token_char)
+}
+
+/// Checks `method SP request-target SP HTTP-version`, separated by single spaces.
+fn validate_request_line(line: &[u8]) -> io::Result<()> {
+    let mut parts = line.split(|&b| b == b' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(reject(StatusCode::BadRequest, "Malformed request line"));
+    };
+
+    if !is_token(method) {
+        return Err(reject(StatusCode::BadRequest, "Invalid method"));
+    }
+
+    // Origin form, absolute form, authority form for CONNECT, or `*` for OPTIONS
+    let printable = !target.is_empty() && target.iter().all(|&b| (0x21..0x7f).contains(&b));
+    let form = target.starts_with(b"/")
+        || (target == b"*" && method == b"OPTIONS")
+        || target.windows(3).any(|window| window == b"://")
+        || method == b"CONNECT";
+    if !printable || !form {
+        return Err(reject(StatusCode::BadRequest, "Invalid request target"));
+    }
+
+    match version {
+        b"HTTP/1.1" | b"HTTP/1.0" => Ok(()),
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit() =>
+        {
+            Err(reject(StatusCode::HttpVersionNotSupported, "Unsupported HTTP version"))
+        }
+        _ => Err(reject(StatusCode::BadRequest, "Invalid HTTP version")),
+    }
+}
+
+/// Parses the header fields following the request line, rejecting what RFC 9112
+/// forbids: obsolete line folding, whitespace before the colon and control
+/// characters in values.
+fn parse_header_fields<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    http_1_0: bool,
+    limits: &RequestLimits,
+) -> io::Result<Headers> {
+    let mut headers = Headers::new();
+
+    for line in lines {
+        if line.starts_with([' ', '\t']) {
+            return Err(reject(StatusCode::BadRequest, "Obsolete line folding"));
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(reject(StatusCode::BadRequest, "Header field without a colon"));
+        };
+        if !is_token(name.as_bytes()) {
+            return Err(reject(StatusCode::BadRequest, "Invalid header field name"));
+        }
+        if value.bytes().any(|b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+            return Err(reject(StatusCode::BadRequest, "Invalid header field value"));
+        }
+
+        if headers.len() == limits.max_headers {
+            return Err(reject(StatusCode::RequestHeaderFieldsTooLarge, "Too many header fields"));
+        }
+        headers.append(name, value.trim_matches([' ', '\t']));
+    }
+
+    // HTTP/1.1 requests name exactly one host, RFC 9112 section 3.2
+    match headers.get_all("Host").count() {
+        0 if !http_1_0 => Err(reject(StatusCode::BadRequest, "Missing Host header")),
+        0 | 1 => Ok(headers),
+        _ => Err(reject(StatusCode::BadRequest, "Repeated Host header")),
+    }
+}
+
+/// Works out how the body is delimited. Requests whose framing could be read
+/// differently by another server, the root of request smuggling, are refused.
+fn body_framing(headers: &Headers, http_1_0: bool) -> io::Result<BodyFraming> {
+    if headers.contains("Transfer-Encoding") {
+        if headers.contains("Content-Length") {
+            return Err(reject(
+                StatusCode::BadRequest,
+                "Both Content-Length and Transfer-Encoding",
+            ));
+        }
+        if http_1_0 {
+            return Err(reject(StatusCode::BadRequest, "Transfer-Encoding in an HTTP/1.0 request"));
+        }
+
+        let codings: Vec<String> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .collect();
+        if codings.last().map(String::as_str) != Some("chunked") {
+            return Err(reject(StatusCode::BadRequest, "chunked is not the final transfer coding"));
+        }
+        if codings.len() > 1 {
+            return Err(reject(StatusCode::NotImplemented, "Unsupported transfer coding"));
+        }
+        return Ok(BodyFraming::Chunked);
+    }
+
+    // Repeated Content-Length values are only accepted if they all agree
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|value| value.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(reject(StatusCode::BadRequest, "Invalid Content-Length"));
+        }
+        let value = value
+            .parse::<usize>()
+            .map_err(|_| reject(StatusCode::ContentTooLarge, "Content-Length too large"))?;
+        if length.is_some_and(|length| length != value) {
+            return Err(reject(StatusCode::BadRequest, "Conflicting Content-Length values"));
+        }
+        length = Some(value);
+    }
+
+    Ok(BodyFraming::Length(length.unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds a whole request to a fresh parser and returns the status it is refused with.
+    fn rejection(request: &str, limits: &RequestLimits) -> Option<StatusCode> {
+        match RequestParser::new().advance(request.as_bytes(), limits) {
+            Ok(_) => None,
+            Err(e) => RequestError::status_of(&e),
+        }
+    }
+
+    fn status(request: &str) -> Option<StatusCode> {
+        rejection(request, &RequestLimits::default())
+    }
+
+    #[test]
+    fn frames_a_request_split_across_reads() {
+        let request = b"POST /form HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        let limits = RequestLimits::default();
+        let mut parser = RequestParser::new();
+
+        for end in 1..request.len() {
+            assert!(parser.advance(&request[..end], &limits).unwrap().is_none());
+        }
+        let framed = parser.advance(request, &limits).unwrap().unwrap();
+        assert_eq!(framed.head_length, request.len() - 5);
+        assert_eq!(framed.length, request.len());
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn decodes_a_chunked_body() {
+        let request = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let framed = RequestParser::new()
+            .advance(request.as_bytes(), &RequestLimits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(framed.length, request.len());
+        assert_eq!(framed.chunked.unwrap().0, b"abc");
+    }
+
+    #[test]
+    fn rejects_a_long_request_line() {
+        let limits = RequestLimits {
+            max_request_line: 16,
+            ..RequestLimits::default()
+        };
+        assert_eq!(
+            rejection("GET /a-very-long-target HTTP/1.1\r\nHost: a\r\n\r\n", &limits),
+            Some(StatusCode::UriTooLong)
+        );
+        // Refused before the line is complete
+        assert_eq!(rejection("GET /a-very-long-target", &limits), Some(StatusCode::UriTooLong));
+    }
+
+    #[test]
+    fn rejects_a_large_header_section() {
+        let limits = RequestLimits {
+            max_header_size: 32,
+            ..RequestLimits::default()
+        };
+        let value = "x".repeat(64);
+        assert_eq!(
+            rejection(&format!("GET / HTTP/1.1\r\nHost: a\r\nX-Big: {}\r\n\r\n", value), &limits),
+            Some(StatusCode::RequestHeaderFieldsTooLarge)
+        );
+        // Refused before the section is complete
+        assert_eq!(
+            rejection(&format!("GET / HTTP/1.1\r\nHost: a\r\nX-Big: {}", value), &limits),
+            Some(StatusCode::RequestHeaderFieldsTooLarge)
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_header_fields() {
+        let limits = RequestLimits {
+            max_headers: 2,
+            ..RequestLimits::default()
+        };
+        assert_eq!(rejection("GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\n\r\n", &limits), None);
+        assert_eq!(
+            rejection("GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\n\r\n", &limits),
+            Some(StatusCode::RequestHeaderFieldsTooLarge)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        assert_eq!(status("GET  / HTTP/1.1\r\nHost: a\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("G(T / HTTP/1.1\r\nHost: a\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("GET * HTTP/1.1\r\nHost: a\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n"), None);
+        assert_eq!(status("GET / HTTP/1\r\nHost: a\r\n\r\n"), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn rejects_unsupported_http_versions() {
+        assert_eq!(
+            status("GET / HTTP/2.0\r\nHost: a\r\n\r\n"),
+            Some(StatusCode::HttpVersionNotSupported)
+        );
+        assert_eq!(status("GET / HTTP/1.0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_header_fields() {
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: a\r\nX-Folded: one\r\n two\r\n\r\n"),
+            Some(StatusCode::BadRequest)
+        );
+        assert_eq!(status("GET / HTTP/1.1\r\nHost : a\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: a\r\nNo-Colon\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: a\r\nX: a\x01b\r\n\r\n"), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn requires_a_single_host() {
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), Some(StatusCode::BadRequest));
+        assert_eq!(status("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n"), Some(StatusCode::BadRequest));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_lengths() {
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd"),
+            Some(StatusCode::BadRequest)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 4\r\n\r\nabcd"),
+            Some(StatusCode::BadRequest)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -3\r\n\r\nabc"),
+            Some(StatusCode::BadRequest)
+        );
+        // Repeated values that agree are accepted
+        assert_eq!(status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 3\r\n\r\nabc"), None);
+    }
+
+    #[test]
+    fn rejects_a_content_length_past_the_address_space() {
+        // The end of the body would wrap around to before the end of the head
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 18446744073709551615\r\n\r\n"),
+            Some(StatusCode::ContentTooLarge)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999999999999999\r\n\r\n"),
+            Some(StatusCode::ContentTooLarge)
+        );
+    }
+
+    #[test]
+    fn rejects_content_length_with_transfer_encoding() {
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"),
+            Some(StatusCode::BadRequest)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"),
+            Some(StatusCode::BadRequest)
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_transfer_codings() {
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n"),
+            Some(StatusCode::NotImplemented)
+        );
+        // Without a final chunked the body length cannot be known
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Some(StatusCode::BadRequest)
+        );
+    }
+
+    #[test]
+    fn rejects_bodies_over_the_limit() {
+        let limits = RequestLimits {
+            max_body_size: Some(2),
+            ..RequestLimits::default()
+        };
+        assert_eq!(
+            rejection("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc", &limits),
+            Some(StatusCode::ContentTooLarge)
+        );
+    }
+}
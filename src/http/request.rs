@@ -3,6 +3,7 @@ use std::str;
 
 use crate::debug;
 use crate::http::headers::Headers;
+use crate::http::{RequestError, StatusCode};
 use crate::http::upload::MultipartFormData;
 
 use crate::http::methods::Method;
@@ -17,6 +18,7 @@ pub struct Request {
     query_params: HashMap<String, String>,
     version: String,
     headers: Headers,
+    trailers: Headers,
     body: Vec<u8>,
     chunked: bool,
     keep_alive: bool,
@@ -24,24 +26,45 @@ pub struct Request {
 
 impl Request {
     fn parse_query_params(path: &str) -> (String, HashMap<String, String>) {
+        match path.split_once('?') {
+            Some((base_path, query)) => (base_path.to_string(), Self::parse_query(query)),
+            None => (path.to_string(), HashMap::new()),
+        }
+    }
+
+    fn parse_query(query: &str) -> HashMap<String, String> {
         let mut params = HashMap::new();
-        if let Some((base_path, query)) = path.split_once('?') {
-            for param in query.split('&') {
-                if let Some((key, value)) = param.split_once('=') {
-                    params.insert(
-                        urlencoding::decode(key)
-                            .unwrap_or_else(|_| key.into())
-                            .into_owned(),
-                        urlencoding::decode(value)
-                            .unwrap_or_else(|_| value.into())
-                            .into_owned(),
-                    );
-                }
+        for param in query.split('&') {
+            if let Some((key, value)) = param.split_once('=') {
+                params.insert(
+                    urlencoding::decode(key)
+                        .unwrap_or_else(|_| key.into())
+                        .into_owned(),
+                    urlencoding::decode(value)
+                        .unwrap_or_else(|_| value.into())
+                        .into_owned(),
+                );
             }
-            (base_path.to_string(), params)
-        } else {
-            (path.to_string(), params)
         }
+        params
+    }
+
+    /// Splits a request target into its decoded path and its query. Targets in
+    /// absolute form, `http://host/path`, are reduced to their path.
+    fn parse_target(target: &str) -> io::Result<(String, &str)> {
+        let target = match target.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+            None => target,
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let path = urlencoding::decode(path).map_err(|_| {
+            io::Error::from(RequestError::new(StatusCode::BadRequest, "Invalid percent-encoding in path"))
+        })?;
+        if path.contains('\0') {
+            return Err(RequestError::new(StatusCode::BadRequest, "NUL byte in path").into());
+        }
+        Ok((path.into_owned(), query))
     }
 
     pub fn query_param(&self, key: &str) -> Option<&String> {
@@ -60,6 +83,10 @@ impl Request {
         self.keep_alive
     }
 
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
     pub fn new(method: Method, path: &str, version: &str) -> Self {
         let (path, query_params) = Self::parse_query_params(path);
         Request {
@@ -68,6 +95,7 @@ impl Request {
             query_params,
             version: version.to_string(),
             headers: Headers::new(),
+            trailers: Headers::new(),
             body: Vec::new(),
             chunked: false,
             keep_alive: false,
@@ -94,6 +122,11 @@ impl Request {
         &self.body
     }
 
+    /// Trailer fields received after a chunked body
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
     pub fn set_headers(&mut self, headers: Headers) {
         self.headers = headers;
     }
@@ -106,6 +139,14 @@ impl Request {
         self.body.extend_from_slice(data);
     }
 
+    /// Sets a body decoded from chunked transfer-encoding. The decoded length is
+    /// recorded as Content-Length for handlers that rely on it.
+    pub fn set_chunked_body(&mut self, body: Vec<u8>, trailers: Headers) {
+        self.headers.insert("Content-Length", &body.len().to_string());
+        self.body = body;
+        self.trailers = trailers;
+    }
+
     pub fn parse(raw_request: &[u8]) -> io::Result<Self> {
         debug!("Parsing request: {}", String::from_utf8_lossy(raw_request));
         // First, find the end of headers (double CRLF)
@@ -138,35 +179,44 @@ impl Request {
         }
 
         // Parse the request line
-        let request_parts: Vec<&str> = lines[0].split_whitespace().collect();
+        let request_parts: Vec<&str> = lines[0].split(' ').collect();
         if request_parts.len() != 3 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid request line",
-            ));
+            return Err(RequestError::new(StatusCode::BadRequest, "Malformed request line").into());
         }
 
         let method = Method::from_str(request_parts[0]);
-        let path = urlencoding::decode(request_parts[1]).unwrap().into_owned();
+        let (path, query) = Self::parse_target(request_parts[1])?;
         let version = request_parts[2];
-        let mut request = Request::new(method, &path, version);
+        let mut request = Request::new(method, "", version);
+        request.path = path;
+        request.query_params = Self::parse_query(query);
 
         // Parse headers
         if lines.len() > 1 {
             let headers = Headers::parse(&lines[1..]);
             request.set_headers(headers.clone());
-            // Check transfer encoding and connection headers
+            // Check transfer encoding header
             request.chunked = headers
                 .get("Transfer-Encoding")
                 .map(|v| v.to_lowercase().contains("chunked"))
                 .unwrap_or(false);
-
-            request.keep_alive = match headers.get("Connection") {
-                Some(conn) => conn.to_lowercase().contains("keep-alive"),
-                None => request.version.contains("1.1"), // HTTP/1.1 defaults to keep-alive
-            };
         }
 
+        // HTTP/1.1 defaults to keep-alive, HTTP/1.0 has to ask for it
+        request.keep_alive = match request.headers.get("Connection") {
+            Some(conn) => {
+                let conn = conn.to_lowercase();
+                if conn.split(',').any(|token| token.trim() == "close") {
+                    false
+                } else if conn.split(',').any(|token| token.trim() == "keep-alive") {
+                    true
+                } else {
+                    request.version == "HTTP/1.1"
+                }
+            }
+            None => request.version == "HTTP/1.1",
+        };
+
         // Set the body as raw bytes (don't try to parse as UTF-8)
         if raw_request.len() > headers_end + 4 {
             // +4 for the CRLFCRLF
@@ -175,20 +225,6 @@ impl Request {
         Ok(request)
     }
 
-    // Helper function to find the end of headers (double CRLF sequence)
-    fn _find_headers_end(bytes: &[u8]) -> Option<usize> {
-        for i in 0..bytes.len() - 3 {
-            if bytes[i] == b'\r'
-                && bytes[i + 1] == b'\n'
-                && bytes[i + 2] == b'\r'
-                && bytes[i + 3] == b'\n'
-            {
-                return Some(i);
-            }
-        }
-        None
-    }
-
     // Method to check if request contains a file upload
     pub fn has_file_upload(&self) -> bool {
         // debug!("Checking if request contains a file upload");
@@ -233,14 +269,5 @@ impl Request {
 
 // Helper function to find the end of headers (double CRLF sequence)
 fn find_headers_end(bytes: &[u8]) -> Option<usize> {
-    for i in 0..bytes.len() - 3 {
-        if bytes[i] == b'\r'
-            && bytes[i + 1] == b'\n'
-            && bytes[i + 2] == b'\r'
-            && bytes[i + 3] == b'\n'
-        {
-            return Some(i);
-        }
-    }
-    None
+    bytes.windows(4).position(|window| window == b"\r\n\r\n")
 }
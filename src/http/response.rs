@@ -1,8 +1,10 @@
 use std::fmt::Write;
+use std::io::{self, Read};
 
 use chrono::Utc;
 
-use crate::http::Headers;
+use crate::error;
+use crate::http::{Body, BodyWriter, Headers};
 
 use super::{cookies::Cookie, status::StatusCode};
 
@@ -11,7 +13,9 @@ pub struct Response {
     status_code: StatusCode,
     status_text: String,
     headers: Headers,
-    body: Vec<u8>,
+    body: Body,
+    /// Set for HEAD requests: the head describes the body, which is not sent
+    body_omitted: bool,
 }
 
 impl Response {
@@ -22,12 +26,12 @@ impl Response {
             status_code,
             status_text,
             headers: Headers::new(),
-            body: Vec::new(),
+            body: Body::Empty,
+            body_omitted: false,
         };
 
         // Add default headers
-        response.headers.add("Server", "Kang");
-        response.headers.add("Connection", "close");
+        response.headers.insert("Server", "Kang");
         response.set_cookie(Cookie::new(
             "KANGSESSID",
             &uuid::Uuid::new_v4().to_string(),
@@ -49,31 +53,56 @@ impl Response {
         &self.status_text
     }
 
+    /// Changes the status, along with its reason phrase.
+    pub fn set_status(&mut self, status_code: StatusCode) {
+        self.status_code = status_code;
+        self.status_text = status_code.to_text();
+    }
+
     pub fn headers(&self) -> &Headers {
         &self.headers
     }
 
-    pub fn body(&self) -> &[u8] {
+    pub fn body(&self) -> &Body {
         &self.body
     }
 
+    /// Sets a header, replacing any value it had.
     pub fn set_header(&mut self, key: &str, value: &str) {
-        self.headers.add(key, value);
+        self.headers.insert(key, value);
+    }
+
+    /// Adds a header value, keeping those already set, as for `Vary`.
+    pub fn append_header(&mut self, key: &str, value: &str) {
+        self.headers.append(key, value);
     }
 
     pub fn set_cookie(&mut self, cookie: Cookie) {
-        self.headers.add("Set-Cookie", &cookie.to_string());
+        self.headers.append("Set-Cookie", &cookie.to_string());
     }
 
     pub fn set_body(&mut self, body: Vec<u8>) {
+        self.set_header("Content-Length", &body.len().to_string());
+        self.body = Body::Bytes(body);
+    }
+
+    /// Sets a body that is produced while the response is written, such as a file
+    /// or CGI output. Framing headers are added when the response is serialized.
+    pub fn set_body_stream(&mut self, body: Body) {
+        self.headers.remove("Content-Length");
         self.body = body;
-        self.set_header("Content-Length", &self.body.len().to_string());
     }
 
     pub fn set_body_string(&mut self, body: &str) {
         self.set_body(body.as_bytes().to_vec());
     }
 
+    /// Sends the head only, as for HEAD requests. The framing headers still
+    /// describe the body a GET would get.
+    pub fn omit_body(&mut self) {
+        self.body_omitted = true;
+    }
+
     pub fn add_cookie(&mut self, cookie: Cookie) {
         let cookie_str = format!(
             "{}={}{}{}{}{}{}",
@@ -85,18 +114,67 @@ impl Response {
             if cookie.secure.unwrap_or(false) { "; Secure" } else { "" },
             if cookie.http_only.unwrap_or(false) { "; HttpOnly" } else { "" }
         );
-        self.headers.add("Set-Cookie", &cookie_str);
+        self.headers.append("Set-Cookie", &cookie_str);
+    }
+
+    /// Splits the response into its serialized head and a writer for its body.
+    ///
+    /// The head announces `Content-Length` if the body's length is known and
+    /// `Transfer-Encoding: chunked` otherwise, unless the status has no body.
+    pub fn into_parts(mut self) -> (Vec<u8>, BodyWriter) {
+        // 1xx, 204 and 304 responses have no body, nor anything announcing one
+        if self.status_code.is_informational()
+            || matches!(self.status_code, StatusCode::NoContent | StatusCode::NotModified)
+        {
+            self.headers.remove("Content-Length");
+            self.headers.remove("Transfer-Encoding");
+            let head = self.head_bytes();
+            return (head, BodyWriter::new(Body::Empty));
+        }
+
+        match self.body.content_length() {
+            Some(length) => {
+                self.headers.remove("Transfer-Encoding");
+                self.set_header("Content-Length", &length.to_string());
+            }
+            None => {
+                self.headers.remove("Content-Length");
+                self.set_header("Transfer-Encoding", "chunked");
+            }
+        }
+
+        let head = self.head_bytes();
+        if self.body_omitted {
+            return (head, BodyWriter::new(Body::Empty));
+        }
+        (head, BodyWriter::new(self.body))
     }
 
-    // Convert response to bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Converts the response to bytes, reading a streamed body to its end.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_bytes(self) -> Vec<u8> {
+        let (mut response_bytes, mut body) = self.into_parts();
+        loop {
+            match body.next_frame() {
+                Ok(Some(frame)) => response_bytes.extend_from_slice(&frame),
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Failed to read response body: {}", e);
+                    break;
+                }
+            }
+        }
+        response_bytes
+    }
+
+    fn head_bytes(&self) -> Vec<u8> {
         let mut response_text = String::new();
 
         // Status line
         writeln!(
             response_text,
             "HTTP/1.1 {} {}\r",
-            self.status_code as u16, self.status_text
+            self.status_code.as_u16(), self.status_text
         )
         .unwrap();
 
@@ -107,10 +185,77 @@ impl Response {
         // Empty line to separate headers from body
         writeln!(response_text, "\r").unwrap();
 
-        // Combine headers and body
-        let mut response_bytes = response_text.into_bytes();
-        response_bytes.extend_from_slice(&self.body);
-        response_bytes
+        response_text.into_bytes()
+    }
+
+    /// Builds a response from CGI output: the header block is read up front, the
+    /// rest is streamed to the client as it is produced.
+    pub fn from_cgi(mut output: impl Read + Send + Sync + 'static) -> io::Result<Self> {
+        let mut head = Vec::new();
+        let mut chunk = [0; 4096];
+
+        let headers_end = loop {
+            if let Some(pos) = head.windows(4).position(|window| window == b"\r\n\r\n") {
+                break Some(pos);
+            }
+            let n = output.read(&mut chunk)?;
+            if n == 0 {
+                break None;
+            }
+            head.extend_from_slice(&chunk[..n]);
+        };
+
+        let mut response = Response::new(StatusCode::Ok);
+        let rest = match headers_end {
+            Some(pos) => {
+                let rest = head.split_off(pos + 4);
+                let head = String::from_utf8_lossy(&head[..pos]);
+                let lines: Vec<&str> = head.lines().collect();
+                // Repeated fields such as Set-Cookie are all kept
+                response.headers.merge(Headers::parse(&lines));
+                response.apply_cgi_status()?;
+                rest
+            }
+            None => {
+                // No headers found, treat everything as body
+                response.set_header("Content-Type", "text/html");
+                head
+            }
+        };
+
+        response.set_body_stream(Body::reader(io::Cursor::new(rest).chain(output)));
+        Ok(response)
+    }
+
+    /// Takes the status from the `Status` header of CGI output, such as
+    /// `Status: 429 Slow Down`, keeping the script's reason phrase. Without one,
+    /// a `Location` header makes the response a 302 as RFC 3875 asks.
+    fn apply_cgi_status(&mut self) -> io::Result<()> {
+        let status = match self.headers.remove("Status") {
+            Some(status) => status,
+            None => {
+                if self.headers.contains("Location") {
+                    self.set_status(StatusCode::Found);
+                }
+                return Ok(());
+            }
+        };
+
+        let (code, reason) = status.trim().split_once(' ').unwrap_or((status.trim(), ""));
+        let status_code = Some(code)
+            .filter(|code| code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|code| code.parse().ok())
+            .and_then(StatusCode::from_u16)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Invalid CGI Status header '{}'", status))
+            })?;
+
+        self.set_status(status_code);
+        let reason = reason.trim();
+        if !reason.is_empty() {
+            self.status_text = reason.to_string();
+        }
+        Ok(())
     }
 }
 
@@ -121,10 +266,10 @@ impl From<String> for Response {
         // Split headers and body on double CRLF
         if let Some((headers, body)) = content.split_once("\r\n\r\n") {
             // Parse headers
-            for line in headers.lines() {
-                if let Some((key, value)) = line.split_once(": ") {
-                    response.set_header(key, value);
-                }
+            let lines: Vec<&str> = headers.lines().collect();
+            response.headers.merge(Headers::parse(&lines));
+            if let Err(e) = response.apply_cgi_status() {
+                error!("{}", e);
             }
             response.set_body_string(body);
         } else {
@@ -136,3 +281,24 @@ impl From<String> for Response {
         response
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgi_cookies_keep_the_session_cookie() {
+        let output = "Content-Type: text/plain\r\nSet-Cookie: theme=dark\r\n\r\nhello";
+
+        for response in [
+            Response::from(output.to_string()),
+            Response::from_cgi(io::Cursor::new(output.as_bytes().to_vec())).unwrap(),
+        ] {
+            let cookies: Vec<&String> = response.headers().get_all("Set-Cookie").collect();
+            assert_eq!(cookies.len(), 2, "{:?}", cookies);
+            assert!(cookies[0].starts_with("KANGSESSID="));
+            assert_eq!(cookies[1], "theme=dark");
+            assert_eq!(response.headers().get("Content-Type").map(String::as_str), Some("text/plain"));
+        }
+    }
+}
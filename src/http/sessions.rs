@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use chrono::{DateTime, Utc, Duration};
 use super::cookies::Cookie;
 use super::request::Request;
@@ -12,6 +13,9 @@ pub struct Session {
     pub last_accessed: DateTime<Utc>
 }
 
+/// A session store shared by every worker serving the same server block
+pub type SharedSessionStore = Arc<Mutex<SessionStore>>;
+
 pub struct SessionStore {
     sessions: HashMap<String, Session>,
     pub session_timeout: Duration,
@@ -84,16 +88,16 @@ impl SessionStore {
     pub fn session_from_request(&mut self, request: &Request) -> &mut Session {
         let session_id = request.headers()
             .get_cookie("session_id")
-            .and_then(|cookie| Some(cookie.value.clone()));
+            .map(|cookie| cookie.value.clone());
 
         match session_id {
             Some(id) => {
                 let session_exists = self.sessions.contains_key(&id);
 
                 if session_exists {
-                    return self.get_session(&id).unwrap();
+                    self.get_session(&id).unwrap()
                 } else {
-                    return self.create_session();
+                    self.create_session()
                 }
             },
             None => self.create_session()
@@ -1,83 +1,164 @@
 use std::fmt::Display;
 
-#[derive(Debug, Clone, Copy)]
-pub enum StatusCode {
-    Ok = 200,
-    Created = 201,
-    NoContent = 204,
-    MultipleChoices = 300,
-    MovedPermenantly = 301,
-    Found = 302,
-    SeeOther = 303,
-    NotModified = 304,
-    UseProxy = 305,
-    Unused = 306,
-    TemporaryRedirect = 307,
-    PermenantRedirect = 308,
-    BadRequest = 400,
-    Unauthorized = 401,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    PayloadTooLarge = 413,
-    InternalServerError = 500,
-    NotImplemented = 501,
+/// Declares `StatusCode` with a variant per registered code, along with the
+/// conversions to and from numbers and reason phrases.
+macro_rules! status_codes {
+    ($($variant:ident = $code:literal, $reason:literal;)+) => {
+        /// An HTTP status code. Every code in the IANA registry has its own
+        /// variant; any other code from 100 to 599 is carried by `Custom`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($variant,)+
+            /// An unregistered code, such as one sent by a CGI script. Only
+            /// `from_u16` builds it, so registered codes get their own variant.
+            Custom(UnregisteredCode),
+        }
+
+        /// Registered codes and their reason phrases, in the order declared
+        #[cfg(test)]
+        const REGISTERED: &[(u16, &str)] = &[$(($code, $reason),)+];
+
+        impl StatusCode {
+            /// Returns the status for a code from 100 to 599, the only valid
+            /// ones, and `None` for anything else.
+            pub fn from_u16(status_code: u16) -> Option<Self> {
+                match status_code {
+                    $($code => Some(StatusCode::$variant),)+
+                    100..=599 => Some(StatusCode::Custom(UnregisteredCode(status_code))),
+                    _ => None,
+                }
+            }
+
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(StatusCode::$variant => $code,)+
+                    StatusCode::Custom(UnregisteredCode(status_code)) => *status_code,
+                }
+            }
+
+            /// The registered reason phrase, or the name of the class for
+            /// unregistered codes.
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(StatusCode::$variant => $reason,)+
+                    StatusCode::Custom(_) => match self.class() {
+                        1 => "Informational",
+                        2 => "Success",
+                        3 => "Redirection",
+                        4 => "Client Error",
+                        _ => "Server Error",
+                    },
+                }
+            }
+        }
+    };
+}
+
+/// A code from 100 to 599 missing from the IANA registry. It has no public
+/// constructor, so a code is never carried by both `Custom` and a variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnregisteredCode(u16);
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+    Processing = 102, "Processing";
+    EarlyHints = 103, "Early Hints";
+
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NonAuthoritativeInformation = 203, "Non-Authoritative Information";
+    NoContent = 204, "No Content";
+    ResetContent = 205, "Reset Content";
+    PartialContent = 206, "Partial Content";
+    MultiStatus = 207, "Multi-Status";
+    AlreadyReported = 208, "Already Reported";
+    ImUsed = 226, "IM Used";
+
+    MultipleChoices = 300, "Multiple Choices";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    UseProxy = 305, "Use Proxy";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    PaymentRequired = 402, "Payment Required";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    ProxyAuthenticationRequired = 407, "Proxy Authentication Required";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    MisdirectedRequest = 421, "Misdirected Request";
+    UnprocessableContent = 422, "Unprocessable Content";
+    Locked = 423, "Locked";
+    FailedDependency = 424, "Failed Dependency";
+    TooEarly = 425, "Too Early";
+    UpgradeRequired = 426, "Upgrade Required";
+    PreconditionRequired = 428, "Precondition Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+    UnavailableForLegalReasons = 451, "Unavailable For Legal Reasons";
+
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+    VariantAlsoNegotiates = 506, "Variant Also Negotiates";
+    InsufficientStorage = 507, "Insufficient Storage";
+    LoopDetected = 508, "Loop Detected";
+    NotExtended = 510, "Not Extended";
+    NetworkAuthenticationRequired = 511, "Network Authentication Required";
 }
 
 impl StatusCode {
-    pub fn from_u16(status_code: u16) -> Option<Self> {
-        match status_code {
-            200 => Some(StatusCode::Ok),
-            201 => Some(StatusCode::Created),
-            204 => Some(StatusCode::NoContent),
-            300 => Some(StatusCode::MultipleChoices),
-            301 => Some(StatusCode::MovedPermenantly),
-            302 => Some(StatusCode::Found),
-            303 => Some(StatusCode::SeeOther),
-            304 => Some(StatusCode::NotModified),
-            305 => Some(StatusCode::UseProxy),
-            306 => Some(StatusCode::Unused),
-            307 => Some(StatusCode::TemporaryRedirect),
-            308 => Some(StatusCode::PermenantRedirect),
-            400 => Some(StatusCode::BadRequest),
-            401 => Some(StatusCode::Unauthorized),
-            403 => Some(StatusCode::Forbidden),
-            404 => Some(StatusCode::NotFound),
-            405 => Some(StatusCode::MethodNotAllowed),
-            413 => Some(StatusCode::PayloadTooLarge),
-            500 => Some(StatusCode::InternalServerError),
-            501 => Some(StatusCode::NotImplemented),
-            _ => None,
-        }
+    pub fn to_text(&self) -> String {
+        self.reason().to_string()
     }
 
-    pub fn as_u16(&self) -> u16 {
-        *self as u16
+    /// The first digit of the code
+    pub fn class(&self) -> u16 {
+        self.as_u16() / 100
     }
 
-    pub fn to_text(&self) -> String {
-        match self {
-            StatusCode::Ok => "OK".to_string(),
-            StatusCode::Created => "Created".to_string(),
-            StatusCode::NoContent => "No Content".to_string(),
-            StatusCode::MultipleChoices => "Multiple Choices".to_string(),
-            StatusCode::MovedPermenantly => "Moved Permanently".to_string(),
-            StatusCode::Found => "Found".to_string(),
-            StatusCode::SeeOther => "See Other".to_string(),
-            StatusCode::NotModified => "Not Modified".to_string(),
-            StatusCode::UseProxy => "Use Proxy".to_string(),
-            StatusCode::Unused => "Unused".to_string(),
-            StatusCode::TemporaryRedirect => "Temporary Redirect".to_string(),
-            StatusCode::PermenantRedirect => "Permenant Redirect".to_string(),
-            StatusCode::BadRequest => "Bad Request".to_string(),
-            StatusCode::Unauthorized => "Unauthorized".to_string(),
-            StatusCode::Forbidden => "Forbidden".to_string(),
-            StatusCode::NotFound => "Not Found".to_string(),
-            StatusCode::MethodNotAllowed => "Method Not Allowed".to_string(),
-            StatusCode::PayloadTooLarge => "Payload Too Large".to_string(),
-            StatusCode::InternalServerError => "Internal Server Error".to_string(),
-            StatusCode::NotImplemented => "Not Implemented".to_string(),
-        }
+    pub fn is_informational(&self) -> bool {
+        self.class() == 1
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        self.class() == 3
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        self.class() == 4
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        self.class() == 5
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.is_client_error() || self.is_server_error()
     }
 }
 
@@ -85,4 +166,39 @@ impl Display for StatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_u16())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_codes_round_trip() {
+        for &(code, reason) in REGISTERED {
+            let status = StatusCode::from_u16(code).unwrap();
+            assert!(!matches!(status, StatusCode::Custom(_)), "{} is registered", code);
+            assert_eq!(status.as_u16(), code);
+            assert_eq!(status.reason(), reason);
+        }
+        assert_eq!(StatusCode::from_u16(200), Some(StatusCode::Ok));
+    }
+
+    #[test]
+    fn unregistered_codes_round_trip() {
+        for code in 100..=599 {
+            let status = StatusCode::from_u16(code).unwrap();
+            assert_eq!(status.as_u16(), code);
+            let registered = REGISTERED.iter().any(|&(registered, _)| registered == code);
+            assert_eq!(matches!(status, StatusCode::Custom(_)), !registered, "{}", code);
+        }
+        assert_eq!(StatusCode::from_u16(299).unwrap().reason(), "Success");
+        assert_eq!(StatusCode::from_u16(599).unwrap().reason(), "Server Error");
+    }
+
+    #[test]
+    fn rejects_codes_out_of_range() {
+        for code in [0, 42, 99, 600, 999, u16::MAX] {
+            assert_eq!(StatusCode::from_u16(code), None);
+        }
+    }
+}
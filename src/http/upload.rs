@@ -55,8 +55,7 @@ impl UploadHandler {
         // Ok(saved_files)
         let mut saved_files = Vec::new();
         // First ensure uploads directory exists
-        let uploads_dir = format!("{}uploads", self.upload_dir);
-        fs::create_dir_all(&uploads_dir)?;
+        fs::create_dir_all(&self.upload_dir)?;
 
         for file in &multipart_data.files {
             // Check file size against max body size
@@ -67,12 +66,21 @@ impl UploadHandler {
                 ));
             }
 
-            // Generate file path
-            let file_path = format!("{}uploads/{}", self.upload_dir, file.filename);
+            // Generate file path, keeping only the file name the client sent
+            let filename = match Path::new(&file.filename).file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid file name '{}'", file.filename),
+                    ))
+                }
+            };
+            let file_path = Path::new(&self.upload_dir).join(&filename);
             // Save file
-            self.save_file(&file_path, &file.content)?;
-            saved_files.push(file.filename.clone());
-            info!("File uploaded successfully: {}", file.filename);
+            self.save_file(&file_path.to_string_lossy(), &file.content)?;
+            saved_files.push(filename);
+            info!("File uploaded successfully: {}", file_path.display());
         }
         Ok(saved_files)
     }
@@ -103,8 +111,7 @@ impl UploadHandler {
         // Verify file was written correctly
         let metadata = fs::metadata(path)?;
         if metadata.len() != content.len() as u64 {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
+            return Err(io::Error::other(
                 format!("File size mismatch: expected {} bytes, got {} bytes", 
                         content.len(), metadata.len())
             ));
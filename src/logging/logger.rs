@@ -1,10 +1,49 @@
 use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 const INFO_PREFIX: &str = "\x1b[1;32m[INFO]\x1b[0m"; // Bold Green
 const WARN_PREFIX: &str = "\x1b[1;33m[WARN]\x1b[0m"; // Bold Yellow
 const ERROR_PREFIX: &str = "\x1b[1;31m[ERROR]\x1b[0m"; // Bold Red
 const DEBUG_PREFIX: &str = "\x1b[1;36m[DEBUG]\x1b[0m"; // Bold Cyan
 
+/// Least severe level that is logged, messages below it are dropped.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!("Unknown log level '{}', expected debug, info, warn or error", level)),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Sets the least severe level logged from now on, by every thread.
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn enabled(level: LogLevel) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
 /// Logs an informational message with format args
 #[macro_export]
 macro_rules! info {
@@ -15,7 +54,9 @@ macro_rules! info {
 
 /// Internal info function called by the macro
 pub fn _info(message: &str) {
-    log(INFO_PREFIX, message);
+    if enabled(LogLevel::Info) {
+        log(INFO_PREFIX, message);
+    }
 }
 
 /// Logs a warning message with format args
@@ -28,7 +69,9 @@ macro_rules! warn {
 
 /// Internal warn function called by the macro
 pub fn _warn(message: &str) {
-    log(WARN_PREFIX, message);
+    if enabled(LogLevel::Warn) {
+        log(WARN_PREFIX, message);
+    }
 }
 
 /// Logs an error message with format args
@@ -56,7 +99,9 @@ macro_rules! debug {
 
 /// Internal debug function called by the macro
 pub fn _debug(message: &str) {
-    log(DEBUG_PREFIX, message);
+    if enabled(LogLevel::Debug) {
+        log(DEBUG_PREFIX, message);
+    }
 }
 
 /// Internal logging function that handles the formatting
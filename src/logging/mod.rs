@@ -2,4 +2,4 @@ pub mod logger;
 
 pub use crate::{debug, error, info, warn};
 
-pub use logger::{_debug, _error, _info, _warn};
+pub use logger::{_debug, _error, _info, _warn, set_level, LogLevel};
@@ -1,15 +1,56 @@
-use kang::{config::boot::KangStarter, info, utils};
-use std::env;
+mod cli;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "config/kangrc".to_string());
+use cli::{Cli, Command, USAGE};
+use kang::config::{boot::KangStarter, Config};
+use kang::logging::{self, LogLevel};
+use kang::{error, info, utils};
+use std::{env, process};
 
-    utils::draw_ascii();
-    info!("Booting Kang Server");
+fn main() {
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("kang: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Some(level) = cli.overrides.log_level {
+        logging::set_level(level);
+    }
 
-    KangStarter::boot_servers(&config_path)?;
+    if let Err(e) = run(&cli) {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.command {
+        Command::Help => println!("{}", USAGE),
+        Command::Version => println!("kang {}", env!("CARGO_PKG_VERSION")),
+        Command::TestConfig => KangStarter::test_config(&cli.config_path, &cli.overrides)?,
+        Command::DumpConfig => {
+            // Warnings are logged on stdout and would end up in the JSON
+            logging::set_level(LogLevel::Error);
+            let config = Config::load(&cli.config_path, &cli.overrides)?;
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        Command::ServeDir => {
+            if cli.banner {
+                utils::draw_ascii();
+            }
+            KangStarter::serve_dir(&cli.serve_dir, &cli.overrides)?;
+        }
+        Command::Reload => KangStarter::signal_reload(&cli.config_path)?,
+        Command::Stop => KangStarter::stop(&cli.config_path)?,
+        Command::Serve => {
+            if cli.banner {
+                utils::draw_ascii();
+            }
+            info!("Booting Kang Server");
+            KangStarter::boot_servers(&cli.config_path, &cli.overrides)?;
+        }
+    }
 
     Ok(())
 }
@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Connections open across every worker of a server, held to `max_connections`.
+///
+/// Each worker reports how many connections it has open after accepting and
+/// once per turn of its event loop, so the total may briefly lag behind while
+/// several workers accept at the same time.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// `usize::MAX` when there is no limit
+    max: AtomicUsize,
+    open: AtomicUsize,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: Option<usize>) -> Self {
+        ConnectionLimit {
+            shared: Arc::new(Shared {
+                max: AtomicUsize::new(max.unwrap_or(usize::MAX)),
+                open: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        match self.shared.max.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            max => Some(max),
+        }
+    }
+
+    /// Changes the limit for every worker sharing it.
+    pub fn set_max(&self, max: Option<usize>) {
+        self.shared.max.store(max.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// How many more connections may be opened.
+    pub fn available(&self) -> usize {
+        let max = self.shared.max.load(Ordering::Relaxed);
+        max.saturating_sub(self.shared.open.load(Ordering::Relaxed))
+    }
+
+    /// Records that a worker went from `before` to `after` open connections.
+    pub fn update(&self, before: usize, after: usize) {
+        if after > before {
+            self.shared.open.fetch_add(after - before, Ordering::Relaxed);
+        } else if before > after {
+            self.shared.open.fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,483 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use crate::http::{BodyWriter, Request, RequestLimits, RequestParser};
+use crate::server::listener::{Stream, TimerWheel};
+use crate::{debug, error, info};
+
+/// Keep-alive policy applied by a listener to every connection it accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// How long an idle connection is kept open. A zero timeout disables keep-alive.
+    pub timeout: Duration,
+    /// Maximum number of requests served over a single connection.
+    pub max_requests: usize,
+}
+
+impl KeepAlive {
+    pub fn is_enabled(&self) -> bool {
+        !self.timeout.is_zero() && self.max_requests > 0
+    }
+}
+
+impl Default for KeepAlive {
+    fn default() -> Self {
+        KeepAlive {
+            timeout: Duration::from_secs(75),
+            max_requests: 100,
+        }
+    }
+}
+
+/// Read and write timeouts applied by a listener to every connection it accepts.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long a client may take to send a complete request, and to send its
+    /// first request after connecting
+    pub read: Duration,
+    /// How long a response may go without the client reading any of it
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            read: Duration::from_secs(60),
+            write: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Which timeout a connection ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Idle between requests for longer than the keep-alive timeout
+    Idle,
+    /// A request was started but not completed in time, answered with 408
+    Read,
+    /// The client stopped reading its response
+    Write,
+}
+
+/// Output queued behind a streamed body.
+#[derive(Debug)]
+pub enum Outgoing {
+    Bytes(Vec<u8>),
+    Body(BodyWriter),
+}
+
+/// A connected client socket together with the state kept between readiness events.
+///
+/// Bytes are accumulated in `buffer` and framed by `parser`, which resumes where
+/// it stopped on the previous event. Once one or more requests are complete they
+/// are split off the front of the buffer, which is what makes pipelining work.
+/// Every listener reads through this type.
+///
+/// Responses are written through the `outbound` buffer: whatever the socket does
+/// not accept right away stays queued until the listener is told the socket is
+/// writable again. Streamed bodies wait in `pending` and are only read once
+/// everything queued before them has been written, so a large file never has
+/// to fit in memory.
+#[derive(Debug)]
+pub struct Connection {
+    pub stream: Stream,
+    pub buffer: Vec<u8>,
+    pub parser: RequestParser,
+    pub requests_served: usize,
+    pub last_active: Instant,
+    /// When the first byte of the request being received arrived
+    pub request_started: Option<Instant>,
+    /// When queued output last made progress
+    pub last_write: Instant,
+    /// Deadline of the entry this connection has in the timer wheel
+    pub timer: Option<Instant>,
+    pub outbound: Vec<u8>,
+    pub written: usize,
+    pub pending: VecDeque<Outgoing>,
+    /// Bytes taken by `take_output` whose asynchronous write has not completed
+    pub in_flight: usize,
+    /// Set once the last response is queued, the connection is closed when it drains
+    pub closing: bool,
+}
+
+impl Connection {
+    pub fn new(stream: Stream) -> Self {
+        Connection {
+            stream,
+            buffer: Vec::new(),
+            parser: RequestParser::new(),
+            requests_served: 0,
+            last_active: Instant::now(),
+            request_started: None,
+            last_write: Instant::now(),
+            timer: None,
+            outbound: Vec::new(),
+            written: 0,
+            pending: VecDeque::new(),
+            in_flight: 0,
+            closing: false,
+        }
+    }
+
+    pub fn touch(&mut self) {
+        self.last_active = Instant::now();
+    }
+
+    /// Returns when this connection times out and which timeout that is.
+    pub fn deadline(&self, keep_alive: &KeepAlive, timeouts: &Timeouts) -> (Instant, Expiry) {
+        if self.has_pending_output() {
+            (self.last_write + timeouts.write, Expiry::Write)
+        } else if let Some(started) = self.request_started {
+            (started + timeouts.read, Expiry::Read)
+        } else if self.requests_served == 0 {
+            // The first request is held to the read timeout, keep-alive only applies between requests
+            (self.last_active + timeouts.read, Expiry::Idle)
+        } else {
+            (self.last_active + keep_alive.timeout, Expiry::Idle)
+        }
+    }
+
+    /// Makes sure the timer wheel holds an entry no later than this connection's deadline.
+    pub fn arm_timer(&mut self, timers: &mut TimerWheel, keep_alive: &KeepAlive, timeouts: &Timeouts) {
+        let (deadline, _) = self.deadline(keep_alive, timeouts);
+        if self.timer.is_none_or(|armed| deadline < armed) {
+            timers.schedule(self.stream.as_raw_fd(), deadline);
+            self.timer = Some(deadline);
+        }
+    }
+
+    /// Handles a timer wheel entry for this connection that came due at `entry`.
+    ///
+    /// Returns the timeout the connection ran into, or `None` if the entry was
+    /// stale or the deadline moved, in which case the timer is armed again.
+    pub fn check_timer(
+        &mut self,
+        entry: Instant,
+        now: Instant,
+        timers: &mut TimerWheel,
+        keep_alive: &KeepAlive,
+        timeouts: &Timeouts,
+    ) -> Option<Expiry> {
+        // A newer entry replaced this one
+        if self.timer != Some(entry) {
+            return None;
+        }
+        self.timer = None;
+
+        let (deadline, expiry) = self.deadline(keep_alive, timeouts);
+        if deadline > now {
+            self.arm_timer(timers, keep_alive, timeouts);
+            return None;
+        }
+        Some(expiry)
+    }
+
+    /// Returns true if no request is being received or answered on this connection.
+    pub fn is_quiet(&self) -> bool {
+        self.parser.is_idle() && self.buffer.is_empty() && !self.has_pending_output()
+    }
+
+    pub fn has_pending_output(&self) -> bool {
+        self.written < self.outbound.len() || !self.pending.is_empty() || self.in_flight > 0
+    }
+
+    /// Appends bytes to the outbound queue without writing anything.
+    pub fn queue(&mut self, bytes: &[u8]) {
+        if !self.has_pending_output() {
+            self.last_write = Instant::now();
+        }
+        if !self.pending.is_empty() {
+            self.pending.push_back(Outgoing::Bytes(bytes.to_vec()));
+            return;
+        }
+        if self.written == self.outbound.len() {
+            self.outbound.clear();
+            self.written = 0;
+        }
+        self.outbound.extend_from_slice(bytes);
+    }
+
+    /// Queues a streamed body behind everything already queued.
+    pub fn queue_body(&mut self, body: BodyWriter) {
+        if !self.has_pending_output() {
+            self.last_write = Instant::now();
+        }
+        self.pending.push_back(Outgoing::Body(body));
+    }
+
+    /// Writes as much queued output as the socket accepts without blocking.
+    ///
+    /// Returns true once the queue is empty.
+    pub fn flush(&mut self) -> io::Result<bool> {
+        loop {
+            if self.written == self.outbound.len() {
+                // Files go straight from the page cache to the socket
+                #[cfg(target_os = "linux")]
+                {
+                    let fd = self.stream.as_raw_fd();
+                    if let Some(Outgoing::Body(body)) = self.pending.front_mut() {
+                        match body.send_file(fd) {
+                            Some(Ok(0)) => {
+                                self.pending.pop_front();
+                                continue;
+                            }
+                            Some(Ok(_)) => {
+                                self.touch();
+                                self.last_write = self.last_active;
+                                continue;
+                            }
+                            Some(Err(e)) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                            Some(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                            Some(Err(e)) => return Err(e),
+                            None => {}
+                        }
+                    }
+                }
+
+                if !self.refill()? {
+                    break;
+                }
+            }
+
+            match self.stream.write(&self.outbound[self.written..]) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Failed to write response",
+                    ))
+                }
+                Ok(n) => {
+                    self.written += n;
+                    self.touch();
+                    self.last_write = self.last_active;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.outbound.clear();
+        self.written = 0;
+        Ok(true)
+    }
+
+    /// Takes the next bytes to write off the queue, for listeners that hand
+    /// them to the kernel instead of writing them through `flush`. They count
+    /// as pending output until the listener clears `in_flight`.
+    pub fn take_output(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.written == self.outbound.len() && !self.refill()? {
+            return Ok(None);
+        }
+
+        let bytes = self.outbound.split_off(self.written);
+        self.outbound.clear();
+        self.written = 0;
+        self.in_flight = bytes.len();
+        Ok(Some(bytes))
+    }
+
+    /// Returns the file body at the front of the queue once everything queued
+    /// before it has been written, for listeners that read files themselves.
+    pub fn pending_file(&mut self) -> Option<&mut BodyWriter> {
+        if self.written < self.outbound.len() {
+            return None;
+        }
+        match self.pending.front_mut() {
+            Some(Outgoing::Body(body)) if body.is_file() => Some(body),
+            _ => None,
+        }
+    }
+
+    /// Moves the next piece of pending output into the outbound buffer.
+    ///
+    /// Returns false if nothing is left to write.
+    fn refill(&mut self) -> io::Result<bool> {
+        self.outbound.clear();
+        self.written = 0;
+
+        while let Some(next) = self.pending.front_mut() {
+            match next {
+                Outgoing::Bytes(bytes) => {
+                    self.outbound = std::mem::take(bytes);
+                    self.pending.pop_front();
+                }
+                Outgoing::Body(body) => match body.next_frame()? {
+                    Some(frame) => self.outbound = frame,
+                    None => {
+                        self.pending.pop_front();
+                        continue;
+                    }
+                },
+            }
+
+            if !self.outbound.is_empty() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reads everything currently available on the socket into the buffer.
+    ///
+    /// Returns true if the peer closed its side of the connection.
+    pub fn fill_buffer(&mut self) -> io::Result<bool> {
+        let mut temp_buf = [0; 4096];
+
+        // Edge-triggered: drain the socket until it would block
+        loop {
+            match self.stream.read(&mut temp_buf) {
+                Ok(0) => return Ok(true),
+                Ok(n) => self.receive(&temp_buf[0..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Appends bytes received from the socket to the buffer.
+    pub fn receive(&mut self, data: &[u8]) {
+        if self.parser.is_idle() && self.buffer.is_empty() && !data.is_empty() {
+            self.request_started = Some(Instant::now());
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Reads from the socket and returns every request completed by the new data,
+    /// in order. Fails with `WouldBlock` if no request is complete yet and with
+    /// `ConnectionAborted` if the peer closed the connection between requests.
+    pub fn read_requests(
+        &mut self,
+        keep_alive: &KeepAlive,
+        limits: &RequestLimits,
+    ) -> io::Result<Vec<Request>> {
+        let fd = self.stream.as_raw_fd();
+        let peer_closed = match self.fill_buffer() {
+            Ok(peer_closed) => peer_closed,
+            Err(e) => {
+                error!("Read error on fd={}: {}", fd, e);
+                return Err(e);
+            }
+        };
+
+        self.touch();
+        self.requests_received(peer_closed, keep_alive, limits)
+    }
+
+    /// Returns every request completed by the data received so far, like
+    /// `read_requests` but without reading from the socket. `peer_closed` tells
+    /// whether the peer has closed its side of the connection.
+    pub fn requests_received(
+        &mut self,
+        peer_closed: bool,
+        keep_alive: &KeepAlive,
+        limits: &RequestLimits,
+    ) -> io::Result<Vec<Request>> {
+        let fd = self.stream.as_raw_fd();
+
+        // The last response is on its way out, nothing else will be answered
+        if self.closing {
+            self.buffer.clear();
+            self.request_started = None;
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Connection is closing",
+            ));
+        }
+
+        let mut requests = self.take_requests(keep_alive, limits)?;
+
+        if peer_closed {
+            match requests.last_mut() {
+                // Answer what was already received, then close
+                Some(last) => last.set_keep_alive(false),
+                None => {
+                    info!("Connection closed by peer fd={}", fd);
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Connection closed",
+                    ));
+                }
+            }
+        }
+
+        if requests.is_empty() {
+            debug!(
+                "Incomplete request on fd={} ({:?}), waiting for more data",
+                fd,
+                self.parser.phase()
+            );
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Incomplete request",
+            ));
+        }
+
+        Ok(requests)
+    }
+
+    /// Splits every complete request off the front of the buffer, in the order they
+    /// were received. Bytes belonging to an incomplete request stay in the buffer.
+    ///
+    /// The keep-alive policy is applied here: the request that reaches
+    /// `max_requests` (or every request, if keep-alive is disabled) is marked as
+    /// the last one on this connection.
+    pub fn take_requests(
+        &mut self,
+        keep_alive: &KeepAlive,
+        limits: &RequestLimits,
+    ) -> io::Result<Vec<Request>> {
+        let mut requests = Vec::new();
+
+        loop {
+            if self.parser.is_idle() {
+                // Clients may send stray CRLFs between pipelined requests
+                let leading = self
+                    .buffer
+                    .iter()
+                    .take_while(|&&b| b == b'\r' || b == b'\n')
+                    .count();
+                self.buffer.drain(..leading);
+                if self.buffer.is_empty() {
+                    self.request_started = None;
+                }
+            }
+
+            let framed = match self.parser.advance(&self.buffer, limits)? {
+                Some(framed) => framed,
+                None => break,
+            };
+
+            let raw: Vec<u8> = self.buffer.drain(..framed.length).collect();
+            // Whatever follows belongs to the next request, its clock starts now
+            self.request_started = (!self.buffer.is_empty()).then(Instant::now);
+            let mut request = match framed.chunked {
+                Some((body, trailers)) => {
+                    let mut request = Request::parse(&raw[..framed.head_length])?;
+                    request.set_chunked_body(body, trailers);
+                    request
+                }
+                None => Request::parse(&raw)?,
+            };
+
+            self.requests_served += 1;
+            if !keep_alive.is_enabled() || self.requests_served >= keep_alive.max_requests {
+                request.set_keep_alive(false);
+            }
+
+            let last = !request.is_keep_alive();
+            requests.push(request);
+            if last {
+                // Anything pipelined after a closing request is never answered
+                self.buffer.clear();
+                self.request_started = None;
+                break;
+            }
+        }
+
+        Ok(requests)
+    }
+}
@@ -0,0 +1,128 @@
+use std::env;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+use crate::config::ListenConfig;
+use crate::warn;
+
+use super::socket;
+use super::stream::ListenSocket;
+
+/// First descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+/// Lists the listening sockets a running kang hands to the process replacing it
+pub const INHERITED_FDS_VAR: &str = "KANG_INHERITED_FDS";
+
+/// Returns the listening sockets this process was started with, passed by
+/// systemd socket activation (`LISTEN_FDS`/`LISTEN_PID`) or by the kang process
+/// it replaces during a binary upgrade.
+///
+/// The variables are removed from the environment so they do not reach
+/// CGI scripts, which must only be done before any thread is started.
+pub fn inherited_sockets() -> Vec<ListenSocket> {
+    let mut fds = systemd_fds();
+    fds.extend(upgrade_fds());
+
+    fds.into_iter()
+        .filter_map(|fd| match socket::from_fd(fd) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!("Ignoring inherited fd {}: {}", fd, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns true if this process was started by a kang handing over its
+/// sockets, as long as `inherited_sockets` has not been called yet.
+pub fn started_by_upgrade() -> bool {
+    env::var_os(INHERITED_FDS_VAR).is_some()
+}
+
+fn systemd_fds() -> Vec<RawFd> {
+    let pid = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<RawFd>().ok());
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+
+    match (pid, count) {
+        // The variables may have been meant for a parent process
+        (Some(pid), Some(count)) if pid == std::process::id() => {
+            (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn upgrade_fds() -> Vec<RawFd> {
+    let fds = env::var(INHERITED_FDS_VAR).unwrap_or_default();
+    env::remove_var(INHERITED_FDS_VAR);
+
+    fds.split(',')
+        .filter(|fd| !fd.is_empty())
+        .filter_map(|fd| match fd.parse() {
+            Ok(fd) => Some(fd),
+            Err(_) => {
+                warn!("Ignoring invalid fd '{}' in {}", fd, INHERITED_FDS_VAR);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns true if `socket` is bound to the address of `listen`.
+pub fn is_bound_to(socket: &ListenSocket, listen: &ListenConfig) -> bool {
+    match (socket, listen.unix_path()) {
+        (ListenSocket::Unix(listener), Some(path)) => listener
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|bound| bound == path))
+            .unwrap_or(false),
+        (ListenSocket::Tcp(listener), None) => {
+            match (listener.local_addr(), listen.address.to_socket_addrs()) {
+                (Ok(local), Ok(mut addrs)) => addrs.any(|addr| addr == local),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Starts a new kang with the same arguments and hands it the listening
+/// sockets `fds`, so it accepts on them while this process drains.
+///
+/// The program is taken from `argv[0]` rather than the running executable, so
+/// a binary replaced on disk is picked up.
+pub fn spawn_upgrade(fds: &[RawFd]) -> io::Result<Child> {
+    let mut args = env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Program name unknown"))?;
+
+    let list = fds
+        .iter()
+        .map(|fd| fd.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    let fds = fds.to_vec();
+    let mut command = Command::new(program);
+    command.args(args).env(INHERITED_FDS_VAR, list);
+    // Runs in the child between fork and exec: only the child's copies lose close-on-exec
+    unsafe {
+        command.pre_exec(move || {
+            for &fd in &fds {
+                if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
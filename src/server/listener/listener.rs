@@ -1,53 +1,139 @@
-use std::{io, os::fd::RawFd};
+use std::{io, os::fd::RawFd, time::Instant};
 
-use crate::http::Request;
+use crate::config::TcpOptions;
+use crate::http::{Request, RequestLimits, Response};
+use crate::server::listener::{KeepAlive, ListenSocket, PollListener, Timeouts};
+use crate::server::poller::{Event, Poller};
 
-#[cfg(target_os = "linux")]
-use crate::server::listener::epoll::EpollListener;
-
-pub const MAX_EVENTS: usize = 1024;
-
-/// Trait for a listener. A listener is a TCP listener that handles connections using I/O Multiplexing
-/// On macOS, it uses the `kqueue` interface, and on Linux, it uses the `epoll` interface.
+/// Trait for a listener. A listener accepts on a TCP or Unix domain socket and handles connections using I/O Multiplexing.
+/// Readiness comes from the server's `Poller`, which the listening socket (see `get_id`)
+/// and every accepted connection are registered with.
 pub trait Listener: Send + Sync {
     fn new(addr: &str) -> io::Result<Self>
     where
         Self: Sized;
+    /// Accepts connections on a socket bound beforehand, see `bind_socket`.
+    fn from_socket(socket: ListenSocket) -> io::Result<Self>
+    where
+        Self: Sized;
+    /// The listening socket, registered with the poller for incoming connections.
     fn get_id(&self) -> RawFd;
-    fn accept_connection(&mut self, global_epoll_fd: RawFd) -> io::Result<()>;
-    fn handle_connection(&mut self, fd: RawFd) -> io::Result<Request>;
-    fn send_bytes(&self, bytes: Vec<u8>, fd: RawFd) -> io::Result<()>;
-    fn remove_connection(&mut self, fd: RawFd, global_epoll_fd: RawFd) -> io::Result<()>;
-    fn get_port(&self) -> u16;
+    /// Accepts pending connections, keeping at most `available` of them open
+    /// and answering the others with 503, and returns the sockets of the
+    /// connections accepted.
+    fn accept_connection(&mut self, poller: &dyn Poller, available: usize) -> io::Result<Vec<RawFd>>;
+    /// Connections that became ready without the poller reporting them, as
+    /// completion-based listeners learn about input while processing their
+    /// completions in `accept_connection`. Checked after every call to it.
+    fn take_ready(&mut self) -> Vec<Event> {
+        Vec::new()
+    }
+    fn connection_count(&self) -> usize;
+    /// Reads whatever is available on `fd` and returns every complete request
+    /// received so far, in order. Fails with `WouldBlock` if none is complete yet.
+    fn handle_connection(&mut self, fd: RawFd) -> io::Result<Vec<Request>>;
+    /// Queues bytes for `fd` and writes as much as the socket accepts without blocking.
+    /// The rest is written from `handle_writable` once the socket is writable again.
+    fn send_bytes(&mut self, bytes: Vec<u8>, fd: RawFd, poller: &dyn Poller) -> io::Result<()>;
+    /// Queues a response like `send_bytes`, reading a streamed body only as the
+    /// socket accepts it.
+    fn send_response(&mut self, response: Response, fd: RawFd, poller: &dyn Poller) -> io::Result<()>;
+    fn handle_writable(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()>;
+    /// Closes `fd` once its outbound queue has drained.
+    fn close_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()>;
+    fn remove_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()>;
+    /// Closes connections whose idle or write timeout has passed. Connections
+    /// that timed out while receiving a request are returned instead, so the
+    /// caller can answer them with 408 before closing them.
+    fn expire_connections(&mut self, poller: &dyn Poller) -> io::Result<Vec<RawFd>>;
+    /// Returns when `expire_connections` may next have something to do.
+    fn next_deadline(&self) -> Option<Instant>;
+    /// Stops accepting and closes connections with nothing in flight. Connections
+    /// still sending a response are closed once it is written.
+    fn begin_drain(&mut self, poller: &dyn Poller) -> io::Result<()>;
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive);
+    /// Options applied to every connection accepted from now on.
+    fn set_tcp_options(&mut self, options: TcpOptions);
+    fn set_request_limits(&mut self, limits: RequestLimits);
+    fn set_timeouts(&mut self, timeouts: Timeouts);
+    /// The address being listened on, written like a `listen` entry.
+    fn local_addr(&self) -> String;
 }
 
-#[cfg(target_os = "linux")]
-impl Listener for EpollListener {
+impl Listener for PollListener {
     fn new(addr: &str) -> io::Result<Self> {
-        EpollListener::new(addr)
+        PollListener::new(addr)
+    }
+
+    fn from_socket(socket: ListenSocket) -> io::Result<Self> {
+        PollListener::from_socket(socket)
     }
 
     fn get_id(&self) -> RawFd {
-        self.epoll_fd
+        self.id
     }
 
-    fn accept_connection(&mut self, global_epoll_fd: RawFd) -> io::Result<()> {
-        self.accept_connection(global_epoll_fd)
+    fn accept_connection(&mut self, poller: &dyn Poller, available: usize) -> io::Result<Vec<RawFd>> {
+        self.accept_connection(poller, available)
     }
 
-    fn handle_connection(&mut self, fd: RawFd) -> io::Result<Request> {
+    fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn handle_connection(&mut self, fd: RawFd) -> io::Result<Vec<Request>> {
         self.handle_connection(fd)
     }
 
-    fn send_bytes(&self, bytes: Vec<u8>, fd: RawFd) -> io::Result<()> {
-        self.send_bytes(bytes, fd)
+    fn send_bytes(&mut self, bytes: Vec<u8>, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        self.send_bytes(bytes, fd, poller)
+    }
+
+    fn send_response(&mut self, response: Response, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        self.send_response(response, fd, poller)
+    }
+
+    fn handle_writable(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        self.handle_writable(fd, poller)
+    }
+
+    fn close_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        self.close_connection(fd, poller)
+    }
+
+    fn remove_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        self.remove_connection(fd, poller)
+    }
+
+    fn expire_connections(&mut self, poller: &dyn Poller) -> io::Result<Vec<RawFd>> {
+        self.expire_connections(poller)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.next_deadline()
+    }
+
+    fn begin_drain(&mut self, poller: &dyn Poller) -> io::Result<()> {
+        self.begin_drain(poller)
+    }
+
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.keep_alive = keep_alive;
+    }
+
+    fn set_tcp_options(&mut self, options: TcpOptions) {
+        self.tcp_options = options;
+    }
+
+    fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.request_limits = limits;
     }
 
-    fn remove_connection(&mut self, fd: RawFd, global_epoll_fd: RawFd) -> io::Result<()> {
-        self.remove_connection(fd, global_epoll_fd)
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
     }
 
-    fn get_port(&self) -> u16 {
-        self.listener.local_addr().port()
+    fn local_addr(&self) -> String {
+        self.addr.clone()
     }
 }
@@ -1,12 +1,23 @@
-#[cfg(target_os = "linux")]
-mod epoll;
-#[cfg(target_os = "macos")]
-mod kqueue;
+mod connection;
+mod inherit;
+mod poll;
+mod socket;
+mod stream;
+mod timer;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring;
+#[allow(clippy::module_inception)]
 mod listener;
 
-#[cfg(target_os = "linux")]
-pub use epoll::EpollListener;
-#[cfg(target_os = "macos")]
-pub use kqueue::KqueueListener;
+pub use poll::PollListener;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+pub use uring::UringListener;
 
-pub use listener::{Listener, MAX_EVENTS};
+pub use connection::{Connection, Expiry, KeepAlive, Outgoing, Timeouts};
+pub use inherit::{
+    inherited_sockets, is_bound_to, spawn_upgrade, started_by_upgrade, INHERITED_FDS_VAR,
+};
+pub use listener::Listener;
+pub use socket::{bind as bind_socket, ReusePort};
+pub use stream::{ListenSocket, Stream};
+pub use timer::TimerWheel;
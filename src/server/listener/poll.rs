@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+
+use crate::config::{ListenConfig, TcpOptions};
+use crate::http::{Request, RequestLimits, Response};
+use crate::server::poller::{Interest, Poller};
+use crate::{debug, error, warn};
+
+use super::connection::{Connection, Expiry, KeepAlive, Timeouts};
+use super::socket;
+use super::stream::ListenSocket;
+use super::timer::TimerWheel;
+
+/// Listening socket whose readiness is reported by a `Poller`.
+///
+/// It contains a non-blocking listener and a map of connected clients. The
+/// listening socket and every client socket are registered with the poller of
+/// the server the listener belongs to, so one event loop serves them all.
+#[derive(Debug)]
+pub struct PollListener {
+    /// Closed once draining starts
+    pub listener: Option<ListenSocket>,
+    /// Descriptor of the listening socket, kept to identify the listener
+    pub id: RawFd,
+    /// Address of the listening socket
+    pub addr: String,
+    pub connections: HashMap<RawFd, Connection>,
+    pub keep_alive: KeepAlive,
+    pub request_limits: RequestLimits,
+    pub timeouts: Timeouts,
+    pub tcp_options: TcpOptions,
+    /// Deadlines of every open connection
+    pub timers: TimerWheel,
+}
+
+impl PollListener {
+    /// Creates a new instance of the server.
+    ///
+    /// # Arguments
+    /// * `addr` - The address to bind the server to.
+    ///
+    /// # Returns
+    /// A new instance of the server.
+    pub fn new(addr: &str) -> io::Result<Self> {
+        let listen = ListenConfig::new(addr);
+        Self::from_socket(socket::bind(&listen, socket::ReusePort::No, &TcpOptions::default())?)
+    }
+
+    /// Creates a listener accepting on an already bound socket.
+    pub fn from_socket(listener: ListenSocket) -> io::Result<Self> {
+        // Create non-blocking listener
+        listener.set_nonblocking(true)?;
+
+        Ok(PollListener {
+            id: listener.as_raw_fd(),
+            addr: listener.local_addr(),
+            listener: Some(listener),
+            connections: HashMap::new(),
+            keep_alive: KeepAlive::default(),
+            request_limits: RequestLimits::default(),
+            timeouts: Timeouts::default(),
+            tcp_options: TcpOptions::default(),
+            timers: TimerWheel::new(),
+        })
+    }
+
+    /// Accepts every pending connection and returns the accepted sockets. Once
+    /// `available` connections have been accepted, further ones are answered
+    /// with 503 and closed right away.
+    pub fn accept_connection(&mut self, poller: &dyn Poller, mut available: usize) -> io::Result<Vec<RawFd>> {
+        let mut accepted = Vec::new();
+        // Nothing to accept once draining closed the socket
+        while let Some(listener) = &self.listener {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    // Draining the backlog keeps the edge-triggered listener armed
+                    if available == 0 {
+                        warn!("Connection limit reached, rejecting connection from {}", addr);
+                        stream.refuse();
+                        continue;
+                    }
+                    available -= 1;
+
+                    stream.set_nonblocking(true)?;
+                    if let Err(e) = socket::configure(&stream, &self.tcp_options) {
+                        warn!("Failed to set TCP options for {}: {}", addr, e);
+                    }
+                    let fd = stream.as_raw_fd();
+
+                    // Write readiness is only watched while the connection has queued output
+                    poller.register(fd, Interest::Read)?;
+
+                    // info!("Accepted connection from {:?} fd={}", addr, fd);
+                    self.connections.insert(fd, Connection::new(stream));
+                    self.arm_timer(fd);
+                    accepted.push(fd);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // No more connections to accept
+                    // info!("No more connections to accept");
+                    break;
+                }
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(accepted)
+    }
+
+    pub fn handle_connection(&mut self, fd: RawFd) -> io::Result<Vec<Request>> {
+        let keep_alive = self.keep_alive;
+        let limits = self.request_limits;
+        let conn = self.connection_mut(fd)?;
+        let result = conn.read_requests(&keep_alive, &limits);
+        self.arm_timer(fd);
+        result
+    }
+
+    /// Closes every connection whose idle or write timeout has passed and returns
+    /// the connections that timed out mid-request, which still need a 408.
+    pub fn expire_connections(&mut self, poller: &dyn Poller) -> io::Result<Vec<RawFd>> {
+        let now = Instant::now();
+        let mut timed_out = Vec::new();
+
+        for (fd, entry) in self.timers.expired(now) {
+            let Some(conn) = self.connections.get_mut(&fd) else {
+                continue;
+            };
+
+            match conn.check_timer(entry, now, &mut self.timers, &self.keep_alive, &self.timeouts) {
+                Some(Expiry::Read) => timed_out.push(fd),
+                Some(expiry) => {
+                    debug!("Closing connection fd={} ({:?} timeout)", fd, expiry);
+                    self.remove_connection(fd, poller)?;
+                }
+                None => {}
+            }
+        }
+        Ok(timed_out)
+    }
+
+    /// Returns when the next connection deadline may be due.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next_wakeup()
+    }
+
+    fn arm_timer(&mut self, fd: RawFd) {
+        if let Some(conn) = self.connections.get_mut(&fd) {
+            conn.arm_timer(&mut self.timers, &self.keep_alive, &self.timeouts);
+        }
+    }
+
+    // queues a response for a specified fd and writes what the socket accepts
+    pub fn send_bytes(&mut self, bytes: Vec<u8>, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        let conn = self.connection_mut(fd)?;
+        let already_waiting = conn.has_pending_output();
+        conn.queue(&bytes);
+        self.start_writing(fd, poller, already_waiting)
+    }
+
+    /// Queues a response; a streamed body is read as the socket drains.
+    pub fn send_response(&mut self, response: Response, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        let (head, body) = response.into_parts();
+        let conn = self.connection_mut(fd)?;
+        let already_waiting = conn.has_pending_output();
+        conn.queue(&head);
+        conn.queue_body(body);
+        self.start_writing(fd, poller, already_waiting)
+    }
+
+    fn start_writing(&mut self, fd: RawFd, poller: &dyn Poller, already_waiting: bool) -> io::Result<()> {
+        // Write readiness is already watched, the queue is flushed when it is reported
+        if already_waiting {
+            return Ok(());
+        }
+
+        let flushed = self.connection_mut(fd)?.flush()?;
+        self.arm_timer(fd);
+        if !flushed {
+            poller.modify(fd, Interest::ReadWrite)?;
+        }
+        Ok(())
+    }
+
+    /// Stops accepting and closes every connection with nothing in flight.
+    pub fn begin_drain(&mut self, poller: &dyn Poller) -> io::Result<()> {
+        // New connections are refused from here on. The socket is closed rather
+        // than shut down, as it may be shared with another process
+        let _ = poller.deregister(self.id);
+        self.listener = None;
+
+        let quiet: Vec<RawFd> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.is_quiet())
+            .map(|(&fd, _)| fd)
+            .collect();
+
+        for fd in quiet {
+            self.remove_connection(fd, poller)?;
+        }
+
+        // Between requests, but a response is still being written
+        for conn in self.connections.values_mut() {
+            if conn.parser.is_idle() && conn.buffer.is_empty() {
+                conn.closing = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Continues writing queued output once the socket is reported writable.
+    pub fn handle_writable(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        let flushed = self.connection_mut(fd)?.flush()?;
+        self.arm_timer(fd);
+        if !flushed {
+            return Ok(());
+        }
+
+        if self.connections[&fd].closing {
+            return self.remove_connection(fd, poller);
+        }
+        poller.modify(fd, Interest::Read)
+    }
+
+    /// Closes a connection once everything queued for it has been written.
+    pub fn close_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        let conn = self.connection_mut(fd)?;
+        if conn.has_pending_output() {
+            conn.closing = true;
+            return Ok(());
+        }
+        self.remove_connection(fd, poller)
+    }
+
+    fn connection_mut(&mut self, fd: RawFd) -> io::Result<&mut Connection> {
+        self.connections
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown connection"))
+    }
+
+    pub fn remove_connection(&mut self, fd: RawFd, poller: &dyn Poller) -> io::Result<()> {
+        let conn = self
+            .connections
+            .remove(&fd)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown connection"))?;
+
+        // Deregistered before dropping the connection closes the socket
+        let result = poller.deregister(fd);
+        drop(conn);
+        // info!("Connection removed: fd={}", fd);
+        result
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::config::{ListenConfig, TcpOptions};
+
+use super::stream::{ListenSocket, Stream};
+
+/// How a TCP socket shares its address with the sockets of other workers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReusePort {
+    /// The only socket on the address
+    No,
+    /// The first socket of a worker group. It is bound without SO_REUSEPORT,
+    /// so an address already in use fails even if its owner set the option,
+    /// and set before listening, so the other workers can join.
+    First,
+    /// The socket of another worker, joining the first one
+    Join,
+}
+
+/// Binds a listening socket to exactly the address of `listen`.
+///
+/// SO_REUSEADDR is set on TCP sockets unless `options` disable it, so a
+/// restart does not fail on connections of the previous process lingering in
+/// TIME_WAIT. Buffer sizes are set before listening, as accepted connections
+/// inherit them. Unless `reuse_port` is `No`, SO_REUSEPORT is set as well so
+/// every worker can bind its own socket to the same address and the kernel
+/// balances incoming connections between them. It has no meaning for Unix
+/// domain sockets, whose workers share one socket instead.
+pub fn bind(listen: &ListenConfig, reuse_port: ReusePort, options: &TcpOptions) -> io::Result<ListenSocket> {
+    let backlog = listen.backlog.unwrap_or(libc::SOMAXCONN);
+    if let Some(path) = listen.unix_path() {
+        return bind_unix(path, backlog).map(ListenSocket::Unix);
+    }
+
+    let addr = listen.address.as_str();
+    let mut last_error = None;
+    for socket_addr in addr.to_socket_addrs()? {
+        match bind_addr(&socket_addr, reuse_port, backlog, listen.ipv6_only, options) {
+            Ok(listener) => return Ok(ListenSocket::Tcp(listener)),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Could not resolve {}", addr),
+        )
+    }))
+}
+
+fn bind_addr(
+    addr: &SocketAddr,
+    reuse_port: ReusePort,
+    backlog: i32,
+    ipv6_only: bool,
+    options: &TcpOptions,
+) -> io::Result<TcpListener> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Owned from here on, so the socket is closed on every error path
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    if options.so_reuseaddr {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, 1)?;
+    }
+    if let Some(size) = options.so_rcvbuf {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, size as libc::c_int)?;
+    }
+    if let Some(size) = options.so_sndbuf {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size as libc::c_int)?;
+    }
+    if reuse_port == ReusePort::Join {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    }
+    // Set explicitly, the system default for dual-stack differs between platforms
+    if addr.is_ipv6() {
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, ipv6_only as libc::c_int)?;
+    }
+
+    let (storage, len) = to_sockaddr(addr);
+    if unsafe { libc::bind(fd, &storage as *const _ as *const libc::sockaddr, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if reuse_port == ReusePort::First {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)?;
+    }
+
+    if unsafe { libc::listen(fd, backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(TcpListener::from(socket))
+}
+
+/// Binds a Unix domain socket at `path`. A socket file left behind by a
+/// previous run is removed first, unless a process still accepts on it.
+fn bind_unix(path: &Path, backlog: i32) -> io::Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::from(io::ErrorKind::AddrInUse));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    // The standard library listens with its own backlog, listening again replaces it
+    if unsafe { libc::listen(listener.as_raw_fd(), backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(listener)
+}
+
+/// Applies the per-connection `options` to an accepted TCP connection. Unix
+/// domain sockets have none of them.
+pub fn configure(stream: &Stream, options: &TcpOptions) -> io::Result<()> {
+    let Stream::Tcp(stream) = stream else {
+        return Ok(());
+    };
+    stream.set_nodelay(options.tcp_nodelay)?;
+
+    let fd = stream.as_raw_fd();
+    if !options.so_keepalive {
+        return Ok(());
+    }
+    set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+
+    #[cfg(target_os = "linux")]
+    let idle_option = libc::TCP_KEEPIDLE;
+    #[cfg(target_os = "macos")]
+    let idle_option = libc::TCP_KEEPALIVE;
+
+    for (option, value) in [
+        (idle_option, options.keepalive_idle),
+        (libc::TCP_KEEPINTVL, options.keepalive_interval),
+        (libc::TCP_KEEPCNT, options.keepalive_count),
+    ] {
+        if let Some(value) = value {
+            set_option(fd, libc::IPPROTO_TCP, option, value as libc::c_int)?;
+        }
+    }
+    Ok(())
+}
+
+/// Takes over a listening socket opened by another process, such as systemd
+/// or the kang process this one replaces. The socket is marked close-on-exec
+/// so it does not leak into CGI scripts.
+pub fn from_fd(fd: RawFd) -> io::Result<ListenSocket> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, reason.to_string());
+
+    if get_option(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid("not a stream socket"));
+    }
+    if get_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("not a listening socket"));
+    }
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if unsafe { libc::getsockname(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET | libc::AF_INET6 => Ok(ListenSocket::Tcp(unsafe { TcpListener::from_raw_fd(fd) })),
+        libc::AF_UNIX => Ok(ListenSocket::Unix(unsafe { UnixListener::from_raw_fd(fd) })),
+        _ => Err(invalid("unsupported address family")),
+    }
+}
+
+fn get_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    if unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn set_option(fd: RawFd, level: libc::c_int, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    if unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            option,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = v4.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(v4.ip().octets()),
+            };
+            #[cfg(target_os = "macos")]
+            {
+                sin.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+            }
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = v6.port().to_be();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: v6.ip().octets(),
+            };
+            sin6.sin6_flowinfo = v6.flowinfo();
+            sin6.sin6_scope_id = v6.scope_id();
+            #[cfg(target_os = "macos")]
+            {
+                sin6.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+            }
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as libc::socklen_t)
+}
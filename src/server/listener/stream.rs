@@ -0,0 +1,152 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Answer to connections beyond the configured connection limits
+const OVERLOADED: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nRetry-After: 1\r\nConnection: close\r\n\r\n";
+
+/// A listening socket, bound to a TCP address or a Unix domain socket path.
+#[derive(Debug)]
+pub enum ListenSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ListenSocket {
+    /// Accepts a pending connection and describes its peer for logging.
+    pub fn accept(&self) -> io::Result<(Stream, String)> {
+        match self {
+            ListenSocket::Tcp(listener) => {
+                let (stream, addr) = listener.accept()?;
+                Ok((Stream::Tcp(stream), addr.to_string()))
+            }
+            ListenSocket::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                Ok((Stream::Unix(stream), "unix socket peer".to_string()))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            ListenSocket::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            ListenSocket::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Another handle to the same socket, for workers that cannot bind their
+    /// own: Unix domain sockets have no equivalent of SO_REUSEPORT.
+    pub fn try_clone(&self) -> io::Result<ListenSocket> {
+        match self {
+            ListenSocket::Tcp(listener) => listener.try_clone().map(ListenSocket::Tcp),
+            ListenSocket::Unix(listener) => listener.try_clone().map(ListenSocket::Unix),
+        }
+    }
+
+    pub fn is_unix(&self) -> bool {
+        matches!(self, ListenSocket::Unix(_))
+    }
+
+    /// The address the socket is bound to, written like a `listen` entry.
+    pub fn local_addr(&self) -> String {
+        match self {
+            ListenSocket::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            ListenSocket::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|path| format!("unix:{}", path.display())))
+                .unwrap_or_else(|| "unix:unknown".to_string()),
+        }
+    }
+
+    /// Wraps a connection accepted on this socket by other means, such as an
+    /// io_uring accept.
+    ///
+    /// # Safety
+    /// `fd` must be an open connected socket owned by nobody else.
+    pub unsafe fn stream_from_raw_fd(&self, fd: RawFd) -> Stream {
+        match self {
+            ListenSocket::Tcp(_) => Stream::Tcp(TcpStream::from_raw_fd(fd)),
+            ListenSocket::Unix(_) => Stream::Unix(UnixStream::from_raw_fd(fd)),
+        }
+    }
+}
+
+impl AsRawFd for ListenSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            ListenSocket::Tcp(listener) => listener.as_raw_fd(),
+            ListenSocket::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// A connected client socket.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Answers 503 without waiting for a request, then closes the connection.
+    /// The response fits in the empty send buffer of a new connection, so
+    /// nothing here waits on the client.
+    pub fn refuse(self) {
+        let fd = self.as_raw_fd();
+        let response = OVERLOADED.as_ptr() as *const libc::c_void;
+        unsafe { libc::send(fd, response, OVERLOADED.len(), libc::MSG_DONTWAIT) };
+
+        // Closing with unread input resets the connection, which may discard the response
+        let mut discard = [0u8; 4096];
+        let buf = discard.as_mut_ptr() as *mut libc::c_void;
+        while unsafe { libc::recv(fd, buf, discard.len(), libc::MSG_DONTWAIT) } > 0 {}
+        unsafe { libc::shutdown(fd, libc::SHUT_WR) };
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+            Stream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+/// Number of slots in the wheel
+const WHEEL_SLOTS: usize = 512;
+/// Time covered by one slot
+const TICK: Duration = Duration::from_millis(100);
+
+/// Hashed timer wheel holding connection deadlines.
+///
+/// Scheduling is O(1) and each call to `expired` only visits the slots whose
+/// time has come. Entries are never removed when a deadline moves: the owner
+/// checks on expiry whether the entry is still current, and reschedules if the
+/// connection's deadline moved later in the meantime.
+#[derive(Debug)]
+pub struct TimerWheel {
+    slots: Vec<Vec<(RawFd, Instant)>>,
+    start: Instant,
+    /// First tick that has not been fully processed yet
+    current_tick: u64,
+    len: usize,
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimerWheel {
+    pub fn new() -> Self {
+        TimerWheel {
+            slots: vec![Vec::new(); WHEEL_SLOTS],
+            start: Instant::now(),
+            current_tick: 0,
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn tick_of(&self, instant: Instant) -> u64 {
+        (instant.saturating_duration_since(self.start).as_nanos() / TICK.as_nanos()) as u64
+    }
+
+    pub fn schedule(&mut self, fd: RawFd, deadline: Instant) {
+        let tick = self.tick_of(deadline).max(self.current_tick);
+        self.slots[tick as usize % WHEEL_SLOTS].push((fd, deadline));
+        self.len += 1;
+    }
+
+    /// Removes and returns every entry whose deadline is at or before `now`,
+    /// earliest deadline first.
+    pub fn expired(&mut self, now: Instant) -> Vec<(RawFd, Instant)> {
+        let now_tick = self.tick_of(now);
+        let mut expired = Vec::new();
+        if self.len == 0 {
+            self.current_tick = now_tick;
+            return expired;
+        }
+
+        // After a long pause every slot is visited once, not once per lost tick
+        let last_tick = now_tick.min(self.current_tick + WHEEL_SLOTS as u64 - 1);
+        for tick in self.current_tick..=last_tick {
+            let slot = &mut self.slots[tick as usize % WHEEL_SLOTS];
+            let mut index = 0;
+            while index < slot.len() {
+                if slot[index].1 <= now {
+                    expired.push(slot.swap_remove(index));
+                } else {
+                    index += 1;
+                }
+            }
+        }
+
+        // The current tick stays open, entries in it may not be due yet
+        self.current_tick = now_tick;
+        self.len -= expired.len();
+        // Slots are unordered, and a pause may have wrapped past several turns
+        expired.sort_by_key(|&(_, deadline)| deadline);
+        expired
+    }
+
+    /// Returns when everything in the next non-empty slot is due. Entries in it
+    /// may belong to a later turn of the wheel, in which case waking up early
+    /// is harmless.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        if self.len == 0 {
+            return None;
+        }
+
+        (0..WHEEL_SLOTS as u64)
+            .map(|offset| self.current_tick + offset)
+            .find(|tick| !self.slots[*tick as usize % WHEEL_SLOTS].is_empty())
+            .map(|tick| self.start + TICK * (tick + 1) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One full turn of the wheel
+    const TURN: Duration = Duration::from_millis(100 * WHEEL_SLOTS as u64);
+
+    #[test]
+    fn does_not_fire_a_deadline_a_turn_away_early() {
+        let mut wheel = TimerWheel::new();
+        let deadline = wheel.start + TURN + Duration::from_millis(250);
+        wheel.schedule(7, deadline);
+
+        // Its slot comes around on the first turn, before the deadline
+        for elapsed in [300, 1000, 30_000, 51_199] {
+            let now = wheel.start + Duration::from_millis(elapsed);
+            assert!(wheel.expired(now).is_empty(), "fired after {}ms", elapsed);
+        }
+        assert!(!wheel.is_empty());
+
+        assert!(wheel.expired(deadline - Duration::from_millis(1)).is_empty());
+        assert_eq!(wheel.expired(deadline), [(7, deadline)]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn fires_a_deadline_a_turn_away_after_a_long_pause() {
+        let mut wheel = TimerWheel::new();
+        let deadline = wheel.start + TURN + Duration::from_millis(250);
+        wheel.schedule(7, deadline);
+
+        let now = wheel.start + TURN * 3;
+        assert_eq!(wheel.expired(now), [(7, deadline)]);
+    }
+
+    #[test]
+    fn rearming_later_keeps_the_connection() {
+        let mut wheel = TimerWheel::new();
+        let first = wheel.start + Duration::from_millis(500);
+        let later = wheel.start + Duration::from_millis(2500);
+        wheel.schedule(7, first);
+
+        // The first entry comes due and its owner finds the deadline moved
+        assert_eq!(wheel.expired(first), [(7, first)]);
+        wheel.schedule(7, later);
+
+        assert!(wheel.expired(first + Duration::from_millis(1000)).is_empty());
+        assert!(wheel.next_wakeup().is_some_and(|wakeup| wakeup <= later + TICK));
+        assert_eq!(wheel.expired(later), [(7, later)]);
+    }
+
+    #[test]
+    fn rearming_earlier_leaves_a_stale_entry() {
+        let mut wheel = TimerWheel::new();
+        let later = wheel.start + Duration::from_millis(2500);
+        let earlier = wheel.start + Duration::from_millis(500);
+        wheel.schedule(7, later);
+        wheel.schedule(7, earlier);
+
+        assert_eq!(wheel.expired(earlier), [(7, earlier)]);
+        // Handed out again once due, for the owner to recognize as stale
+        assert_eq!(wheel.expired(later), [(7, later)]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn drains_expired_entries_in_order() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let at = |millis| start + Duration::from_millis(millis);
+        // Several share a slot, some are a turn away and one is not due
+        let deadlines = [
+            (1, at(950)),
+            (2, at(120)),
+            (3, at(910)),
+            (4, at(100)),
+            (5, at(51_300)),
+            (6, at(80_000)),
+            (7, at(51_250)),
+            (8, at(990)),
+        ];
+        for (fd, deadline) in deadlines {
+            wheel.schedule(fd, deadline);
+        }
+
+        let drained: Vec<RawFd> = wheel.expired(at(960)).into_iter().map(|(fd, _)| fd).collect();
+        assert_eq!(drained, [4, 2, 3, 1]);
+
+        let drained: Vec<RawFd> = wheel.expired(at(60_000)).into_iter().map(|(fd, _)| fd).collect();
+        assert_eq!(drained, [8, 7, 5]);
+        assert!(!wheel.is_empty());
+    }
+}
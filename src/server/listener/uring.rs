@@ -0,0 +1,630 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::Instant;
+
+use io_uring::{opcode, squeue, types, IoUring, Probe};
+
+use crate::config::{ListenConfig, TcpOptions};
+use crate::http::{Request, RequestLimits, Response};
+use crate::server::poller::{Event, Poller};
+use crate::{debug, error, warn};
+
+use super::connection::{Connection, Expiry, KeepAlive, Timeouts};
+use super::socket;
+use super::stream::ListenSocket;
+use super::timer::TimerWheel;
+
+/// Submission queue size of each ring
+const RING_ENTRIES: u32 = 1024;
+/// Size of the buffer each connection receives into
+const RECV_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Operation kinds, stored in the low bits of an entry's user data next to the fd
+const OP_ACCEPT: u64 = 0;
+const OP_RECV: u64 = 1;
+const OP_SEND: u64 = 2;
+const OP_READ: u64 = 3;
+const OP_CANCEL: u64 = 4;
+const OP_BITS: u64 = 3;
+
+fn user_data(fd: RawFd, op: u64) -> u64 {
+    ((fd as u64) << OP_BITS) | op
+}
+
+/// A connection together with the buffers lent to the kernel for it.
+///
+/// The kernel writes into and reads from these buffers until the matching
+/// completion arrives, so a slot is only dropped, and its socket closed, once
+/// no operation is in flight anymore.
+struct Slot {
+    conn: Connection,
+    recv_buf: Vec<u8>,
+    /// Buffer of the send or file read in flight
+    send_buf: Vec<u8>,
+    /// How much of `send_buf` the kernel has already sent
+    sent: usize,
+    /// Operations submitted for this connection that have not completed
+    in_flight: usize,
+    sending: bool,
+    peer_closed: bool,
+    error: Option<io::Error>,
+    /// Closed by the server, dropped once the last completion arrives
+    removed: bool,
+}
+
+impl Slot {
+    fn new(conn: Connection) -> Self {
+        Slot {
+            conn,
+            recv_buf: vec![0; RECV_BUFFER_SIZE],
+            send_buf: Vec::new(),
+            sent: 0,
+            in_flight: 0,
+            sending: false,
+            peer_closed: false,
+            error: None,
+            removed: false,
+        }
+    }
+}
+
+/// TCP listening socket that accepts, receives, sends and reads files through
+/// an io_uring instead of waiting for readiness.
+///
+/// The ring's file descriptor is what gets registered with the server's
+/// poller: it becomes readable whenever completions are waiting, which are
+/// processed by `accept_connection`. Connections that received data are then
+/// handed to the server through `take_ready`, and from there on requests are
+/// answered like on any other listener.
+pub struct UringListener {
+    ring: IoUring,
+    /// Closed once draining starts
+    listener: Option<ListenSocket>,
+    /// Descriptor of the listening socket, which tags accept completions
+    listen_fd: RawFd,
+    /// Address of the listening socket
+    addr: String,
+    slots: HashMap<RawFd, Slot>,
+    keep_alive: KeepAlive,
+    request_limits: RequestLimits,
+    timeouts: Timeouts,
+    tcp_options: TcpOptions,
+    /// Deadlines of every open connection
+    timers: TimerWheel,
+    /// An accept is in flight
+    accepting: bool,
+    draining: bool,
+    /// Connections with new input, see `take_ready`
+    ready: Vec<Event>,
+    /// Connections accepted while processing completions
+    accepted: Vec<RawFd>,
+}
+
+impl UringListener {
+    /// Returns true if the kernel supports io_uring and every operation this listener submits.
+    pub fn is_supported() -> bool {
+        let Ok(ring) = IoUring::new(2) else {
+            return false;
+        };
+
+        let mut probe = Probe::new();
+        if ring.submitter().register_probe(&mut probe).is_err() {
+            return false;
+        }
+
+        [
+            opcode::Accept::CODE,
+            opcode::Recv::CODE,
+            opcode::Send::CODE,
+            opcode::Read::CODE,
+            opcode::AsyncCancel::CODE,
+        ]
+        .into_iter()
+        .all(|code| probe.is_supported(code))
+    }
+
+    pub fn new(addr: &str) -> io::Result<Self> {
+        let listen = ListenConfig::new(addr);
+        Self::from_socket(socket::bind(&listen, socket::ReusePort::No, &TcpOptions::default())?)
+    }
+
+    /// Creates a listener accepting on an already bound socket.
+    pub fn from_socket(listener: ListenSocket) -> io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+
+        let mut uring = UringListener {
+            ring,
+            listen_fd: listener.as_raw_fd(),
+            addr: listener.local_addr(),
+            listener: Some(listener),
+            slots: HashMap::new(),
+            keep_alive: KeepAlive::default(),
+            request_limits: RequestLimits::default(),
+            timeouts: Timeouts::default(),
+            tcp_options: TcpOptions::default(),
+            timers: TimerWheel::new(),
+            accepting: false,
+            draining: false,
+            ready: Vec::new(),
+            accepted: Vec::new(),
+        };
+        uring.submit_accept()?;
+        uring.ring.submit()?;
+        Ok(uring)
+    }
+
+    fn push(&mut self, entry: squeue::Entry) -> io::Result<()> {
+        loop {
+            // The buffers an entry points to live in `slots` until its completion arrives
+            if unsafe { self.ring.submission().push(&entry) }.is_ok() {
+                return Ok(());
+            }
+            // Queue full: hand what is queued to the kernel to make room
+            self.ring.submit()?;
+        }
+    }
+
+    fn submit_accept(&mut self) -> io::Result<()> {
+        if self.accepting || self.draining {
+            return Ok(());
+        }
+
+        let fd = self.listen_fd;
+        let entry = opcode::Accept::new(types::Fd(fd), ptr::null_mut(), ptr::null_mut())
+            .flags(libc::SOCK_CLOEXEC)
+            .build()
+            .user_data(user_data(fd, OP_ACCEPT));
+        self.push(entry)?;
+        self.accepting = true;
+        Ok(())
+    }
+
+    fn submit_recv(&mut self, fd: RawFd) -> io::Result<()> {
+        let Some(slot) = self.slots.get_mut(&fd) else {
+            return Ok(());
+        };
+        if slot.removed || slot.peer_closed || slot.error.is_some() {
+            return Ok(());
+        }
+
+        let entry = opcode::Recv::new(
+            types::Fd(fd),
+            slot.recv_buf.as_mut_ptr(),
+            slot.recv_buf.len() as u32,
+        )
+        .build()
+        .user_data(user_data(fd, OP_RECV));
+        slot.in_flight += 1;
+        self.push(entry)
+    }
+
+    /// Submits the next write for a connection: the rest of a partial send, a
+    /// read of the next piece of a file body, or the next queued bytes.
+    fn submit_output(&mut self, fd: RawFd) -> io::Result<()> {
+        let Some(slot) = self.slots.get_mut(&fd) else {
+            return Ok(());
+        };
+        if slot.sending || slot.removed {
+            return Ok(());
+        }
+
+        let entry = if slot.sent < slot.send_buf.len() {
+            let remaining = &slot.send_buf[slot.sent..];
+            opcode::Send::new(types::Fd(fd), remaining.as_ptr(), remaining.len() as u32)
+                .flags(libc::MSG_NOSIGNAL)
+                .build()
+                .user_data(user_data(fd, OP_SEND))
+        } else if let Some((file, offset, len)) =
+            slot.conn.pending_file().and_then(|body| body.next_file_read())
+        {
+            slot.send_buf.resize(len, 0);
+            slot.sent = 0;
+            opcode::Read::new(types::Fd(file), slot.send_buf.as_mut_ptr(), len as u32)
+                .offset(offset)
+                .build()
+                .user_data(user_data(fd, OP_READ))
+        } else {
+            match slot.conn.take_output()? {
+                Some(bytes) => {
+                    slot.send_buf = bytes;
+                    slot.sent = 0;
+                    opcode::Send::new(types::Fd(fd), slot.send_buf.as_ptr(), slot.send_buf.len() as u32)
+                        .flags(libc::MSG_NOSIGNAL)
+                        .build()
+                        .user_data(user_data(fd, OP_SEND))
+                }
+                None => {
+                    // Everything was written
+                    slot.conn.in_flight = 0;
+                    return Ok(());
+                }
+            }
+        };
+
+        slot.sending = true;
+        slot.in_flight += 1;
+        self.push(entry)
+    }
+
+    /// Processes every completion the kernel has posted, including those of
+    /// operations submitted along the way.
+    fn reap(&mut self, available: &mut usize) -> io::Result<()> {
+        loop {
+            let completions: Vec<(u64, i32)> = self
+                .ring
+                .completion()
+                .map(|cqe| (cqe.user_data(), cqe.result()))
+                .collect();
+            if completions.is_empty() {
+                return Ok(());
+            }
+
+            self.complete(completions, available)?;
+            self.ring.submit()?;
+        }
+    }
+
+    fn complete(&mut self, completions: Vec<(u64, i32)>, available: &mut usize) -> io::Result<()> {
+        for (data, result) in completions {
+            let fd = (data >> OP_BITS) as RawFd;
+            match data & ((1 << OP_BITS) - 1) {
+                OP_ACCEPT => self.complete_accept(result, available)?,
+                OP_RECV => self.complete_recv(fd, result)?,
+                OP_SEND => self.complete_send(fd, result)?,
+                OP_READ => self.complete_read(fd, result)?,
+                _ => {}
+            }
+            self.release(fd);
+        }
+        Ok(())
+    }
+
+    /// Stops accepting: the pending accept is cancelled and none is submitted again.
+    fn stop_accepting(&mut self) -> io::Result<()> {
+        self.draining = true;
+        if !self.accepting {
+            return Ok(());
+        }
+
+        let fd = self.listen_fd;
+        let entry = opcode::AsyncCancel::new(user_data(fd, OP_ACCEPT))
+            .build()
+            .user_data(user_data(fd, OP_CANCEL));
+        self.push(entry)?;
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    fn complete_accept(&mut self, result: i32, available: &mut usize) -> io::Result<()> {
+        self.accepting = false;
+        if result < 0 {
+            let e = io::Error::from_raw_os_error(-result);
+            if !self.draining {
+                error!("Accept error: {}", e);
+            }
+        } else {
+            let Some(listener) = self.listener.as_ref().filter(|_| !self.draining) else {
+                // Accepted just before the accept was cancelled
+                unsafe { libc::close(result) };
+                return Ok(());
+            };
+            let stream = unsafe { listener.stream_from_raw_fd(result) };
+            if *available == 0 {
+                warn!("Connection limit reached, rejecting connection on {}", self.addr);
+                stream.refuse();
+            } else {
+                *available -= 1;
+                if let Err(e) = socket::configure(&stream, &self.tcp_options) {
+                    warn!("Failed to set TCP options on {}: {}", self.addr, e);
+                }
+                let fd = stream.as_raw_fd();
+                self.slots.insert(fd, Slot::new(Connection::new(stream)));
+                self.accepted.push(fd);
+                self.arm_timer(fd);
+                self.submit_recv(fd)?;
+            }
+        }
+        self.submit_accept()
+    }
+
+    fn complete_recv(&mut self, fd: RawFd, result: i32) -> io::Result<()> {
+        let Some(slot) = self.slots.get_mut(&fd) else {
+            return Ok(());
+        };
+        slot.in_flight -= 1;
+        if slot.removed {
+            return Ok(());
+        }
+
+        match result {
+            0 => slot.peer_closed = true,
+            n if n > 0 => {
+                let received = &slot.recv_buf[..n as usize];
+                slot.conn.receive(received);
+            }
+            _ => slot.error = Some(io::Error::from_raw_os_error(-result)),
+        }
+        slot.conn.touch();
+
+        self.ready.push(Event {
+            fd,
+            readable: true,
+            writable: false,
+        });
+        self.submit_recv(fd)
+    }
+
+    fn complete_send(&mut self, fd: RawFd, result: i32) -> io::Result<()> {
+        let Some(slot) = self.slots.get_mut(&fd) else {
+            return Ok(());
+        };
+        slot.in_flight -= 1;
+        slot.sending = false;
+        if slot.removed {
+            return Ok(());
+        }
+
+        if result < 0 {
+            warn!("Write error on fd={}: {}", fd, io::Error::from_raw_os_error(-result));
+            return self.remove(fd);
+        }
+
+        slot.sent += result as usize;
+        slot.conn.touch();
+        slot.conn.last_write = slot.conn.last_active;
+        if slot.sent == slot.send_buf.len() {
+            slot.conn.in_flight = 0;
+        }
+
+        self.submit_output(fd)?;
+        self.arm_timer(fd);
+
+        let slot = &self.slots[&fd];
+        if slot.conn.closing && !slot.conn.has_pending_output() {
+            return self.remove(fd);
+        }
+        Ok(())
+    }
+
+    fn complete_read(&mut self, fd: RawFd, result: i32) -> io::Result<()> {
+        let Some(slot) = self.slots.get_mut(&fd) else {
+            return Ok(());
+        };
+        slot.in_flight -= 1;
+        slot.sending = false;
+        if slot.removed {
+            return Ok(());
+        }
+
+        if result <= 0 {
+            let e = match result {
+                0 => io::Error::new(io::ErrorKind::UnexpectedEof, "File shorter than its announced length"),
+                _ => io::Error::from_raw_os_error(-result),
+            };
+            error!("Failed to read file for fd={}: {}", fd, e);
+            return self.remove(fd);
+        }
+
+        let n = result as usize;
+        if let Some(body) = slot.conn.pending_file() {
+            body.advance_file(n);
+        }
+        // The piece read is sent next, and counts as queued output until it is
+        slot.send_buf.truncate(n);
+        slot.conn.in_flight = n;
+        self.submit_output(fd)
+    }
+
+    /// Drops a removed connection once the kernel no longer uses its buffers.
+    fn release(&mut self, fd: RawFd) {
+        if self
+            .slots
+            .get(&fd)
+            .is_some_and(|slot| slot.removed && slot.in_flight == 0)
+        {
+            self.slots.remove(&fd);
+        }
+    }
+
+    /// Closes a connection. Operations still in flight fail once the socket is
+    /// shut down, and the slot is dropped when the last of them completes.
+    fn remove(&mut self, fd: RawFd) -> io::Result<()> {
+        let slot = self
+            .slots
+            .get_mut(&fd)
+            .filter(|slot| !slot.removed)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown connection"))?;
+
+        slot.removed = true;
+        unsafe { libc::shutdown(fd, libc::SHUT_RDWR) };
+        self.release(fd);
+        Ok(())
+    }
+
+    fn slot_mut(&mut self, fd: RawFd) -> io::Result<&mut Slot> {
+        self.slots
+            .get_mut(&fd)
+            .filter(|slot| !slot.removed)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Unknown connection"))
+    }
+
+    fn arm_timer(&mut self, fd: RawFd) {
+        if let Some(slot) = self.slots.get_mut(&fd) {
+            slot.conn
+                .arm_timer(&mut self.timers, &self.keep_alive, &self.timeouts);
+        }
+    }
+
+    fn queue_output(&mut self, fd: RawFd) -> io::Result<()> {
+        self.submit_output(fd)?;
+        self.arm_timer(fd);
+        self.ring.submit()?;
+        Ok(())
+    }
+}
+
+impl Drop for UringListener {
+    fn drop(&mut self) {
+        // Wait for the kernel to let go of every buffer before they are freed
+        if self.stop_accepting().is_err() {
+            return;
+        }
+        for (&fd, slot) in self.slots.iter_mut() {
+            slot.removed = true;
+            unsafe { libc::shutdown(fd, libc::SHUT_RDWR) };
+        }
+
+        let mut available = 0;
+        while self.accepting || self.slots.values().any(|slot| slot.in_flight > 0) {
+            if self.ring.submit_and_wait(1).is_err() || self.reap(&mut available).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl super::listener::Listener for UringListener {
+    fn new(addr: &str) -> io::Result<Self> {
+        UringListener::new(addr)
+    }
+
+    fn from_socket(socket: ListenSocket) -> io::Result<Self> {
+        UringListener::from_socket(socket)
+    }
+
+    fn get_id(&self) -> RawFd {
+        self.ring.as_raw_fd()
+    }
+
+    /// Processes the completions waiting on the ring, accepting at most
+    /// `available` new connections.
+    fn accept_connection(&mut self, _poller: &dyn Poller, mut available: usize) -> io::Result<Vec<RawFd>> {
+        self.reap(&mut available)?;
+        Ok(std::mem::take(&mut self.accepted))
+    }
+
+    fn take_ready(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.ready)
+    }
+
+    fn connection_count(&self) -> usize {
+        self.slots.values().filter(|slot| !slot.removed).count()
+    }
+
+    fn handle_connection(&mut self, fd: RawFd) -> io::Result<Vec<Request>> {
+        let keep_alive = self.keep_alive;
+        let limits = self.request_limits;
+        let slot = self.slot_mut(fd)?;
+        if let Some(e) = slot.error.take() {
+            error!("Read error on fd={}: {}", fd, e);
+            return Err(e);
+        }
+
+        let peer_closed = slot.peer_closed;
+        let result = slot.conn.requests_received(peer_closed, &keep_alive, &limits);
+        self.arm_timer(fd);
+        result
+    }
+
+    fn send_bytes(&mut self, bytes: Vec<u8>, fd: RawFd, _poller: &dyn Poller) -> io::Result<()> {
+        self.slot_mut(fd)?.conn.queue(&bytes);
+        self.queue_output(fd)
+    }
+
+    fn send_response(&mut self, response: Response, fd: RawFd, _poller: &dyn Poller) -> io::Result<()> {
+        let (head, body) = response.into_parts();
+        let conn = &mut self.slot_mut(fd)?.conn;
+        conn.queue(&head);
+        conn.queue_body(body);
+        self.queue_output(fd)
+    }
+
+    /// Writes are driven by completions, nothing waits for writability.
+    fn handle_writable(&mut self, fd: RawFd, _poller: &dyn Poller) -> io::Result<()> {
+        self.slot_mut(fd).map(|_| ())
+    }
+
+    fn close_connection(&mut self, fd: RawFd, _poller: &dyn Poller) -> io::Result<()> {
+        let conn = &mut self.slot_mut(fd)?.conn;
+        if conn.has_pending_output() {
+            conn.closing = true;
+            return Ok(());
+        }
+        self.remove(fd)
+    }
+
+    fn remove_connection(&mut self, fd: RawFd, _poller: &dyn Poller) -> io::Result<()> {
+        self.remove(fd)
+    }
+
+    fn expire_connections(&mut self, _poller: &dyn Poller) -> io::Result<Vec<RawFd>> {
+        let now = Instant::now();
+        let mut timed_out = Vec::new();
+
+        for (fd, entry) in self.timers.expired(now) {
+            let Some(slot) = self.slots.get_mut(&fd).filter(|slot| !slot.removed) else {
+                continue;
+            };
+
+            match slot
+                .conn
+                .check_timer(entry, now, &mut self.timers, &self.keep_alive, &self.timeouts)
+            {
+                Some(Expiry::Read) => timed_out.push(fd),
+                Some(expiry) => {
+                    debug!("Closing connection fd={} ({:?} timeout)", fd, expiry);
+                    self.remove(fd)?;
+                }
+                None => {}
+            }
+        }
+        Ok(timed_out)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.timers.next_wakeup()
+    }
+
+    fn begin_drain(&mut self, _poller: &dyn Poller) -> io::Result<()> {
+        // New connections are refused from here on. The socket is closed rather
+        // than shut down, as it may be shared with another process
+        self.stop_accepting()?;
+        self.listener = None;
+
+        let fds: Vec<RawFd> = self.slots.keys().copied().collect();
+        for fd in fds {
+            let Some(slot) = self.slots.get_mut(&fd).filter(|slot| !slot.removed) else {
+                continue;
+            };
+            if slot.conn.is_quiet() {
+                self.remove(fd)?;
+            } else if slot.conn.parser.is_idle() && slot.conn.buffer.is_empty() {
+                // Between requests, but a response is still being written
+                slot.conn.closing = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_keep_alive(&mut self, keep_alive: KeepAlive) {
+        self.keep_alive = keep_alive;
+    }
+
+    fn set_tcp_options(&mut self, options: TcpOptions) {
+        self.tcp_options = options;
+    }
+
+    fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.request_limits = limits;
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    fn local_addr(&self) -> String {
+        self.addr.clone()
+    }
+}
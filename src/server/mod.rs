@@ -1,8 +1,18 @@
+pub mod limit;
 pub mod listener;
+pub mod poller;
+#[allow(clippy::module_inception)]
 pub mod server;
 pub mod router;
+pub mod signals;
+pub mod table;
+pub mod vhost;
 
+pub use limit::*;
 pub use listener::*;
+pub use poller::*;
 pub use server::*;
 pub use router::*;
+pub use table::*;
+pub use vhost::*;
 
@@ -0,0 +1,98 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use super::poller::{Event, Interest, Poller, MAX_EVENTS};
+
+/// Poller backed by an epoll instance, every descriptor registered edge-triggered.
+#[derive(Debug)]
+pub struct EpollPoller {
+    epoll_fd: RawFd,
+    events: Vec<libc::epoll_event>,
+}
+
+impl EpollPoller {
+    pub fn new() -> io::Result<Self> {
+        let epoll_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(EpollPoller {
+            epoll_fd,
+            events: vec![libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS],
+        })
+    }
+
+    fn control(&self, op: libc::c_int, fd: RawFd, interest: Interest) -> io::Result<()> {
+        let mut events = libc::EPOLLIN | libc::EPOLLET;
+        if interest == Interest::ReadWrite {
+            events |= libc::EPOLLOUT;
+        }
+
+        let mut event = libc::epoll_event {
+            events: events as u32,
+            u64: fd as u64,
+        };
+
+        if unsafe { libc::epoll_ctl(self.epoll_fd, op, fd, &mut event) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Poller for EpollPoller {
+    fn register(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, interest)
+    }
+
+    fn modify(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, interest)
+    }
+
+    fn deregister(&self, fd: RawFd) -> io::Result<()> {
+        if unsafe {
+            libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut())
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        // Rounded up, so the caller does not spin until a deadline is due
+        let timeout_ms = timeout.map_or(-1, |timeout| {
+            timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+        });
+
+        let nfds = unsafe {
+            libc::epoll_wait(
+                self.epoll_fd,
+                self.events.as_mut_ptr(),
+                self.events.len() as i32,
+                timeout_ms,
+            )
+        };
+
+        events.clear();
+        if nfds < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let readable = (libc::EPOLLIN | libc::EPOLLHUP | libc::EPOLLERR) as u32;
+        events.extend(self.events[..nfds as usize].iter().map(|event| Event {
+            fd: event.u64 as RawFd,
+            readable: event.events & readable != 0,
+            writable: event.events & libc::EPOLLOUT as u32 != 0,
+        }));
+        Ok(())
+    }
+}
+
+impl Drop for EpollPoller {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.epoll_fd) };
+    }
+}
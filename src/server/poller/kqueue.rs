@@ -0,0 +1,122 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::time::Duration;
+
+use super::poller::{Event, Interest, Poller, MAX_EVENTS};
+
+/// Poller backed by a kqueue. Filters are added with EV_CLEAR, so readiness is
+/// reported edge-triggered like on epoll.
+#[derive(Debug)]
+pub struct KqueuePoller {
+    kqueue_fd: RawFd,
+    events: Vec<libc::kevent>,
+}
+
+fn change(fd: RawFd, filter: i16, flags: u16) -> libc::kevent {
+    libc::kevent {
+        ident: fd as usize,
+        filter,
+        flags,
+        fflags: 0,
+        data: 0,
+        udata: ptr::null_mut(),
+    }
+}
+
+impl KqueuePoller {
+    pub fn new() -> io::Result<Self> {
+        let kqueue_fd = unsafe { libc::kqueue() };
+        if kqueue_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(KqueuePoller {
+            kqueue_fd,
+            events: vec![change(0, 0, 0); MAX_EVENTS],
+        })
+    }
+
+    fn apply(&self, changes: &[libc::kevent]) -> io::Result<()> {
+        if unsafe {
+            libc::kevent(
+                self.kqueue_fd,
+                changes.as_ptr(),
+                changes.len() as i32,
+                ptr::null_mut(),
+                0,
+                ptr::null(),
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Deletes the write filter, which is only present while output is queued.
+    fn remove_write_filter(&self, fd: RawFd) -> io::Result<()> {
+        match self.apply(&[change(fd, libc::EVFILT_WRITE, libc::EV_DELETE)]) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl Poller for KqueuePoller {
+    fn register(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        self.apply(&[change(fd, libc::EVFILT_READ, libc::EV_ADD | libc::EV_CLEAR)])?;
+        self.modify(fd, interest)
+    }
+
+    fn modify(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        match interest {
+            Interest::Read => self.remove_write_filter(fd),
+            Interest::ReadWrite => {
+                self.apply(&[change(fd, libc::EVFILT_WRITE, libc::EV_ADD | libc::EV_CLEAR)])
+            }
+        }
+    }
+
+    fn deregister(&self, fd: RawFd) -> io::Result<()> {
+        self.apply(&[change(fd, libc::EVFILT_READ, libc::EV_DELETE)])?;
+        self.remove_write_filter(fd)
+    }
+
+    fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        });
+
+        let nevents = unsafe {
+            libc::kevent(
+                self.kqueue_fd,
+                ptr::null(),
+                0,
+                self.events.as_mut_ptr(),
+                self.events.len() as i32,
+                timeout.as_ref().map_or(ptr::null(), |timeout| timeout as *const _),
+            )
+        };
+
+        events.clear();
+        if nevents < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Each kevent reports a single filter
+        events.extend(self.events[..nevents as usize].iter().map(|event| Event {
+            fd: event.ident as RawFd,
+            readable: event.filter == libc::EVFILT_READ,
+            writable: event.filter == libc::EVFILT_WRITE,
+        }));
+        Ok(())
+    }
+}
+
+impl Drop for KqueuePoller {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.kqueue_fd) };
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use super::poller::{Event, Interest, Poller};
+
+/// One step of a script, called on `wait` until it returns `None`
+type Step = Box<dyn FnMut(&MockPoller) -> Option<Vec<Event>>>;
+
+/// Poller for tests, handing out scripted events instead of waiting on the kernel.
+///
+/// Each call to `wait` runs the next step of the script, which sees what is
+/// registered and returns the events to report. Once the script is exhausted
+/// `wait` fails, which ends the event loop driven by it.
+pub struct MockPoller {
+    registered: RefCell<HashMap<RawFd, Interest>>,
+    script: VecDeque<Step>,
+}
+
+impl Default for MockPoller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockPoller {
+    pub fn new() -> Self {
+        MockPoller {
+            registered: RefCell::new(HashMap::new()),
+            script: VecDeque::new(),
+        }
+    }
+
+    /// Adds a step reporting the events it returns on a single `wait`.
+    pub fn then(&mut self, step: impl FnOnce(&MockPoller) -> Vec<Event> + 'static) {
+        let mut step = Some(step);
+        self.script
+            .push_back(Box::new(move |poller| step.take().map(|step| step(poller))));
+    }
+
+    /// Adds a step run on every `wait` until it returns `None`, when the next
+    /// step takes over.
+    pub fn repeat(&mut self, step: impl FnMut(&MockPoller) -> Option<Vec<Event>> + 'static) {
+        self.script.push_back(Box::new(step));
+    }
+
+    /// The interest `fd` is registered with, `None` if it is not registered.
+    pub fn interest(&self, fd: RawFd) -> Option<Interest> {
+        self.registered.borrow().get(&fd).copied()
+    }
+
+    /// Every registered file descriptor except `except`, in ascending order.
+    pub fn registered_except(&self, except: RawFd) -> Vec<RawFd> {
+        let mut fds: Vec<RawFd> = self
+            .registered
+            .borrow()
+            .keys()
+            .copied()
+            .filter(|&fd| fd != except)
+            .collect();
+        fds.sort_unstable();
+        fds
+    }
+}
+
+impl Poller for MockPoller {
+    fn register(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        match self.registered.borrow_mut().insert(fd, interest) {
+            Some(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "Already registered")),
+            None => Ok(()),
+        }
+    }
+
+    fn modify(&self, fd: RawFd, interest: Interest) -> io::Result<()> {
+        match self.registered.borrow_mut().get_mut(&fd) {
+            Some(current) => {
+                *current = interest;
+                Ok(())
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Not registered")),
+        }
+    }
+
+    fn deregister(&self, fd: RawFd) -> io::Result<()> {
+        match self.registered.borrow_mut().remove(&fd) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "Not registered")),
+        }
+    }
+
+    /// Runs the script instead of waiting, `timeout` is ignored.
+    fn wait(&mut self, events: &mut Vec<Event>, _timeout: Option<Duration>) -> io::Result<()> {
+        while let Some(mut step) = self.script.pop_front() {
+            if let Some(reported) = step(self) {
+                self.script.push_front(step);
+                *events = reported;
+                return Ok(());
+            }
+        }
+        Err(io::Error::other("Mock poller script exhausted"))
+    }
+}
@@ -0,0 +1,23 @@
+#[cfg(target_os = "linux")]
+mod epoll;
+#[cfg(target_os = "macos")]
+mod kqueue;
+#[cfg(test)]
+mod mock;
+#[allow(clippy::module_inception)]
+mod poller;
+
+#[cfg(target_os = "linux")]
+pub use epoll::EpollPoller;
+#[cfg(target_os = "macos")]
+pub use kqueue::KqueuePoller;
+#[cfg(test)]
+pub use mock::MockPoller;
+
+pub use poller::{Event, Interest, Poller, MAX_EVENTS};
+
+/// The poller used on this platform
+#[cfg(target_os = "linux")]
+pub type SystemPoller = EpollPoller;
+#[cfg(target_os = "macos")]
+pub type SystemPoller = KqueuePoller;
@@ -0,0 +1,42 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/// Most events returned by a single call to `Poller::wait`
+pub const MAX_EVENTS: usize = 1024;
+
+/// Which readiness a registered file descriptor is watched for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    /// Also watched for writability, while a connection has output queued
+    ReadWrite,
+}
+
+/// Readiness reported for one registered file descriptor.
+///
+/// Errors and hang-ups are reported as readable, so the next read surfaces them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub fd: RawFd,
+    pub readable: bool,
+    pub writable: bool,
+}
+
+/// I/O multiplexing interface the event loop and the listeners are written against.
+///
+/// Readiness is edge-triggered: an event is only reported again once new data
+/// arrives or the socket becomes writable again, so callers read, write and
+/// accept until the operation would block. On Linux this is implemented with
+/// epoll and on macOS with kqueue; tests can drive the event loop with a mock
+/// implementation that hands out scripted events.
+pub trait Poller {
+    fn register(&self, fd: RawFd, interest: Interest) -> io::Result<()>;
+    /// Changes the interest of an already registered file descriptor.
+    fn modify(&self, fd: RawFd, interest: Interest) -> io::Result<()>;
+    fn deregister(&self, fd: RawFd) -> io::Result<()>;
+    /// Waits until a registered file descriptor is ready or `timeout` has passed,
+    /// `None` waiting indefinitely. `events` is replaced with what was reported,
+    /// at most `MAX_EVENTS` at a time.
+    fn wait(&mut self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()>;
+}
@@ -1,6 +1,8 @@
-use super::route::Route;
+use super::route::{join_below, Route};
 use crate::config::{Config, ServerConfig};
+use crate::http::methods::Method;
 use crate::http::{Request, Response, StatusCode};
+use crate::utils::parse_size;
 use crate::{debug, error, info};
 use std::fs;
 use std::path::PathBuf;
@@ -45,7 +47,7 @@ impl Mux {
         }
     }
 
-    fn handle_error(&self, status: StatusCode) -> Response {
+    pub fn handle_error(&self, status: StatusCode) -> Response {
         let mut res = Response::new(status);
         let content = self.config
             .error_pages
@@ -59,34 +61,46 @@ impl Mux {
         res
     }
 
+    /// Returns the body size limit for a route: the route's own limit, else the
+    /// server's, else the global one.
+    fn body_size_limit(&self, route: &Route) -> Option<u64> {
+        route
+            .client_max_body_size
+            .as_ref()
+            .or(self.config.client_max_body_size.as_ref())
+            .or(route.config.global.client_max_body_size.as_ref())
+            .and_then(|size| parse_size(size))
+    }
+
     /// Adds a route to the Mux.
     pub fn add_route(&mut self, route: Route) {
         self.routes.push(route);
     }
 
     /// Validates the request by checking if the request matches a route and if the method is allowed.
-    /// Returns the route if the request is valid, otherwise returns a status code.
-    fn validate_request(&self, request: &Request) -> Result<Route, StatusCode> {
+    /// Returns the route if the request is valid, otherwise returns the error response, which lists
+    /// the methods of every route matching the path when none allows the method.
+    fn validate_request(&self, request: &Request) -> Result<Route, Response> {
         info!(
             "Validating request: {} {}",
             request.method(),
             request.path()
         );
-        let mut path_matched = false;
+        let mut path_matched: Vec<&Route> = Vec::new();
         let request_path = request.path().trim_end_matches('/');
 
         // Sort routes by path length in descending order to match most specific routes first
         let mut routes = self.routes.clone();
-        routes.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
+        routes.sort_by_key(|r| std::cmp::Reverse(r.path.len()));
 
         for route in &routes {
             // debug!("validating against: {:?}", route.path.clone());
             let route_path = route.path.trim_end_matches('/');
             
             // Special case for root path
-            if route_path == "" && request_path == "" {
-                path_matched = true;
-                if route.methods.contains(&request.method().as_str().to_string()) {
+            if route_path.is_empty() && request_path.is_empty() {
+                path_matched.push(route);
+                if route.allows(request.method()) {
                     info!("Request matched root route: {}", request.method());
                     return Ok(route.clone());
                 }
@@ -96,8 +110,8 @@ impl Mux {
             // For non-root paths, ensure exact match or proper path separation
             // Handle exact match case
             if request_path == route_path {
-                path_matched = true;
-                if route.methods.contains(&request.method().as_str().to_string()) {
+                path_matched.push(route);
+                if route.allows(request.method()) {
                     info!("Request matched exact route: {} {}", request.method(), route.path);
                     return Ok(route.clone());
                 }
@@ -111,7 +125,7 @@ impl Mux {
                 format!("{}/", route_path)
             };
 
-            if route_path != "" { // prevent root path from matching everything
+            if !route_path.is_empty() { // prevent root path from matching everything
                 // For file serving routes (those with root_dir), allow nested paths
                 let is_file_route = route.root.is_some();
                 let path_matches = if is_file_route {
@@ -122,46 +136,97 @@ impl Mux {
                 };
 
                 if path_matches {
-                    path_matched = true;
-                    if route.methods.contains(&request.method().as_str().to_string()) {
+                    path_matched.push(route);
+                    if route.allows(request.method()) {
                         info!("Request matched route: {} {}", request.method(), route.path);
                         return Ok(route.clone());
                     }
                 }
             }
 
-            // Check if this route can handle file operations
-            if let Some(root_dir) = &route.root {
-                let file_path = PathBuf::from(root_dir).join(request_path.trim_start_matches('/'));
+            // Check if this route can handle file operations, never looking
+            // outside of its root
+            if let Some(file_path) = route.root.as_deref().and_then(|root| join_below(root, request_path)) {
                 debug!("Checking file existence: {:?}", file_path);
-                if file_path.exists() && file_path.is_file() {
+                // Directories too, for listings and index files below the route
+                if file_path.is_file() || (!request_path.is_empty() && file_path.is_dir()) {
                     debug!("File exists: {:?}", file_path);
-                    path_matched = true;
-                    if route.methods.contains(&request.method().as_str().to_string()) {
+                    path_matched.push(route);
+                    if route.allows(request.method()) {
                         info!("Request matched file operation: {} {} (route: {})", request.method(), request_path, route.path);
                         return Ok(route.clone());
                     }
                 }
             }
+
+            // A single page app handles every path below its route
+            if route.spa_fallback && (route_path.is_empty() || request_path.starts_with(&route_with_slash)) {
+                path_matched.push(route);
+                if route.allows(request.method()) {
+                    info!("Request matched single page app: {} {} (route: {})", request.method(), request_path, route.path);
+                    return Ok(route.clone());
+                }
+            }
         }
 
-        if path_matched {
-            Err(StatusCode::MethodNotAllowed)
-        } else {
-            Err(StatusCode::NotFound)
+        if path_matched.is_empty() {
+            return Err(self.handle_error(StatusCode::NotFound));
         }
+        let mut response = self.handle_error(StatusCode::MethodNotAllowed);
+        response.set_header("Allow", &allowed_methods(path_matched).join(", "));
+        Err(response)
     }
 
     /// Handles an incoming HTTP request by routing it to the appropriate handler.
     /// If the request matches a route, the route's handler is called.
     /// If the request does not match any route, a 404 Not Found response is returned.
+    /// Responses to HEAD requests carry the headers of the GET response only.
     pub fn handle(&self, request: Request) -> Response {
+        let head = request.method() == &Method::HEAD;
+        let mut response = self.route(request);
+        if head {
+            response.omit_body();
+        }
+        response
+    }
+
+    fn route(&self, request: Request) -> Response {
+        // `OPTIONS *` asks about the server rather than a resource
+        if request.path() == "*" {
+            let mut response = Response::new(StatusCode::NoContent);
+            response.set_header("Allow", &allowed_methods(&self.routes).join(", "));
+            return response;
+        }
+
         match self.validate_request(&request) {
-            Ok(route) => match route.handle(request) {
-                Ok(response) => response,
-                Err(status) => self.handle_error(status),
-            },
-            Err(status) => self.handle_error(status),
+            Ok(route)
+                if self
+                    .body_size_limit(&route)
+                    .is_some_and(|limit| request.body().len() as u64 > limit) =>
+            {
+                self.handle_error(StatusCode::ContentTooLarge)
+            }
+            Ok(route) => {
+                let origin = request.headers().get("Origin").cloned();
+                let mut response = match route.handle(request) {
+                    Ok(response) => response,
+                    Err(status) => self.handle_error(status),
+                };
+                route.add_cors_headers(origin.as_deref(), &mut response);
+                response
+            }
+            Err(response) => response,
+        }
+    }
+}
+
+/// Every method answered by one of `routes`, in the order they are listed.
+fn allowed_methods<'a>(routes: impl IntoIterator<Item = &'a Route>) -> Vec<&'a str> {
+    let mut allowed = Vec::new();
+    for method in routes.into_iter().flat_map(Route::allowed_methods) {
+        if !allowed.contains(&method) {
+            allowed.push(method);
         }
     }
+    allowed
 }
@@ -3,12 +3,12 @@ use crate::http::files::FileServer;
 use crate::warn;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use crate::{
     cgi::php::PhpExecContext,
-    config::{Config, RouteConfig},
+    config::{Config, CorsConfig, RouteConfig},
+    http::conditional::{self, Validators},
     http::methods::Method,
     http::upload::UploadHandler,
     http::{status::StatusCode, Request, Response},
@@ -26,6 +26,9 @@ pub struct Route {
     pub client_max_body_size: Option<String>,
     pub config: Config,
     pub sessions_required: bool,
+    pub upload_dir: Option<String>,
+    pub spa_fallback: bool,
+    pub cors: Option<CorsConfig>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,28 +38,124 @@ pub struct Redirect {
 }
 
 impl Route {
+    /// The methods the route answers, as listed in `Allow`: HEAD comes with
+    /// GET, and OPTIONS is answered by every route.
+    pub fn allowed_methods(&self) -> Vec<&str> {
+        let mut allowed: Vec<&str> = self.methods.iter().map(String::as_str).collect();
+        if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+            allowed.push("HEAD");
+        }
+        if !allowed.contains(&"OPTIONS") {
+            allowed.push("OPTIONS");
+        }
+        allowed
+    }
+
+    /// Returns true if the route accepts `method`.
+    pub fn allows(&self, method: &Method) -> bool {
+        self.allowed_methods().contains(&method.as_str())
+    }
+
+    /// The CORS settings of the route, if enabled.
+    fn cors(&self) -> Option<&CorsConfig> {
+        self.cors.as_ref().filter(|cors| cors.enabled)
+    }
+
     pub fn handle(&self, request: Request) -> Result<Response, StatusCode> {
         // Check if method is allowed
-        if !self
-            .methods
-            .contains(&request.method().as_str().to_string())
-        {
+        if !self.allows(request.method()) {
             return Err(StatusCode::MethodNotAllowed);
         }
 
-        if let Some(_) = &self.redirect {
-            return self.handle_redirect();
-        } else if let Some(_) = &self.cgi {
-            return self.handle_cgi(request);
+        if request.method() == &Method::OPTIONS {
+            Ok(self.handle_options(&request))
+        } else if self.redirect.is_some() {
+            self.handle_redirect()
+        } else if self.cgi.is_some() {
+            self.handle_cgi(request)
+        } else {
+            self.handle_static(request)
+        }
+    }
+
+    /// Answers OPTIONS with the methods of the route, along with the CORS
+    /// headers when it is a preflight.
+    fn handle_options(&self, request: &Request) -> Response {
+        let mut response = Response::new(StatusCode::NoContent);
+        response.set_header("Allow", &self.allowed_methods().join(", "));
+
+        if request.headers().contains("Access-Control-Request-Method") {
+            self.add_preflight_headers(request, &mut response);
+        }
+        response
+    }
+
+    /// Answers a CORS preflight with the methods of the route and the headers
+    /// the browser asked for.
+    fn add_preflight_headers(&self, request: &Request, response: &mut Response) {
+        let Some(cors) = self.cors() else {
+            return;
+        };
+
+        let methods = if cors.allowed_methods.is_empty() {
+            &self.methods
+        } else {
+            &cors.allowed_methods
+        };
+        response.set_header("Access-Control-Allow-Methods", &methods.join(", "));
+
+        if !cors.allowed_headers.is_empty() {
+            response.set_header("Access-Control-Allow-Headers", &cors.allowed_headers.join(", "));
+        } else if let Some(headers) = request.headers().get("Access-Control-Request-Headers") {
+            response.set_header("Access-Control-Allow-Headers", headers);
+        }
+
+        if let Some(max_age) = cors.max_age {
+            response.set_header("Access-Control-Max-Age", &max_age.to_string());
+        }
+    }
+
+    /// Adds the CORS headers of the route to a response to a cross-origin
+    /// request, one carrying an `Origin` header.
+    pub fn add_cors_headers(&self, origin: Option<&str>, response: &mut Response) {
+        let (Some(cors), Some(origin)) = (self.cors(), origin) else {
+            return;
+        };
+
+        if cors.allowed_origins.iter().any(|allowed| allowed == "*") {
+            response.set_header("Access-Control-Allow-Origin", "*");
         } else {
-            return self.handle_static(request);
+            // Name the origin that matched, and let caches know the answer varies
+            if cors.allowed_origins.iter().any(|allowed| allowed == origin) {
+                response.set_header("Access-Control-Allow-Origin", origin);
+            }
+            response.append_header("Vary", "Origin");
         }
     }
 
+    /// Maps the request path to a file below `base`, refusing paths that
+    /// climb out of it with `..`.
+    fn file_path(&self, base: &str, request: &Request) -> Result<PathBuf, StatusCode> {
+        // Get the relative path by removing the route path prefix
+        let relative_path = request
+            .path()
+            .strip_prefix(&self.path)
+            .unwrap_or(request.path());
+
+        join_below(base, relative_path).ok_or_else(|| {
+            warn!("Refusing path outside of the root: {}", request.path());
+            StatusCode::Forbidden
+        })
+    }
+
     fn handle_redirect(&self) -> Result<Response, StatusCode> {
-        let mut response =
-            Response::new(StatusCode::from_u16(self.redirect.as_ref().unwrap().code).unwrap());
-        response.set_header("Location", &self.redirect.as_ref().unwrap().url);
+        let redirect = self.redirect.as_ref().ok_or(StatusCode::InternalServerError)?;
+        let status = StatusCode::from_u16(redirect.code).ok_or_else(|| {
+            error!("Redirect code {} of route '{}' is not a status code", redirect.code, self.path);
+            StatusCode::InternalServerError
+        })?;
+        let mut response = Response::new(status);
+        response.set_header("Location", &redirect.url);
         Ok(response)
     }
 
@@ -77,16 +176,22 @@ impl Route {
             None => return Err(StatusCode::NotImplemented),
         };
 
-        // Get script path
-        let script_path = match &self.root {
-            Some(root) => format!("{}{}", root, self.path),
-            None => return Err(StatusCode::InternalServerError),
+        // Get script path, named by the route. The request path may not climb
+        // out of the root either, even though it is not used to find the script
+        let root = self.root.as_ref().ok_or(StatusCode::InternalServerError)?;
+        let script_path = match (join_below(root, &self.path), join_below(root, request.path())) {
+            (Some(script_path), Some(_)) => script_path,
+            _ => {
+                warn!("Refusing path outside of the root: {}", request.path());
+                return Err(StatusCode::Forbidden);
+            }
         };
 
         // Check if script exists
-        if !Path::new(&script_path).exists() {
+        if !script_path.exists() {
             return Err(StatusCode::NotFound);
         }
+        let script_path = script_path.to_string_lossy().into_owned();
 
         // Create PHP execution context
         let mut php_ctx = PhpExecContext::new(php_handler.to_string(), script_path);
@@ -94,13 +199,8 @@ impl Route {
         php_ctx.add_env("REQUEST_METHOD", request.method().as_str());
 
         // Execute PHP script
-        match php_ctx.exec() {
-            Ok(output) => {
-                let mut response = Response::new(StatusCode::Ok);
-                response.set_header("Content-Type", "text/html");
-                response.set_body(output.as_bytes().to_vec());
-                Ok(response)
-            }
+        match php_ctx.spawn().and_then(Response::from_cgi) {
+            Ok(response) => Ok(response),
             Err(_) => Err(StatusCode::InternalServerError),
         }
     }
@@ -123,20 +223,21 @@ impl Route {
                 None => return Err(StatusCode::InternalServerError),
             };
 
-            let script_path = format!("{}{}", base_path, request.path());
+            let script_path = self.file_path(base_path, &request)?;
 
             // Check if script exists
-            if !Path::new(&script_path).exists() {
+            if !script_path.exists() {
                 return Err(StatusCode::NotFound);
             }
+            let script_path = script_path.to_string_lossy().into_owned();
 
             // Create PHP execution context
             let mut php_ctx = PhpExecContext::new(php_handler.to_string(), script_path);
             php_ctx.add_env("REQUEST_METHOD", request.method().as_str());
 
             // Execute PHP script
-            match php_ctx.exec() {
-                Ok(output) => return Ok(Response::from(output)),
+            match php_ctx.spawn().and_then(Response::from_cgi) {
+                Ok(response) => return Ok(response),
                 Err(_) => return Err(StatusCode::InternalServerError),
             }
         }
@@ -161,7 +262,11 @@ impl Route {
 
             // Create upload handler with client_max_body_size if specified
             let max_size = self.client_max_body_size.as_deref().unwrap_or("10M");
-            let upload_handler = UploadHandler::new(max_size, base_path);
+            let upload_dir = match &self.upload_dir {
+                Some(upload_dir) => upload_dir.clone(),
+                None => format!("{}uploads", base_path),
+            };
+            let upload_handler = UploadHandler::new(max_size, &upload_dir);
 
             // Handle the upload
             match upload_handler.handle_upload(&multipart_data) {
@@ -185,20 +290,17 @@ impl Route {
                 None => return Err(StatusCode::InternalServerError),
             };
 
-            // Get the relative path by removing the route path prefix
-            let relative_path = request
-                .path()
-                .strip_prefix(&self.path)
-                .unwrap_or(request.path());
-
-            // Construct full path by joining base_path with the relative path
-            let path = PathBuf::from(base_path).join(relative_path.trim_start_matches('/'));
+            let path = self.file_path(base_path, &request)?;
 
             // Check if path exists
             if !path.exists() {
                 return Err(StatusCode::NotFound);
             }
 
+            // Only delete the version the client expects, if it said which
+            let current = fs::metadata(&path).ok().map(|metadata| Validators::from_metadata(&metadata));
+            conditional::evaluate(&request, current.as_ref())?;
+
             // Delete the file
             match fs::remove_file(&path) {
                 Ok(_) => {
@@ -211,6 +313,53 @@ impl Route {
                     Err(StatusCode::InternalServerError)
                 }
             }
+        } else if request.method() == &Method::PUT {
+            let base_path = match &self.root {
+                Some(root) => root,
+                None => return Err(StatusCode::InternalServerError),
+            };
+
+            let path = self.file_path(base_path, &request)?;
+            if path.is_dir() {
+                return Err(StatusCode::Conflict);
+            }
+
+            // If-Match protects against overwriting someone else's changes,
+            // If-None-Match: * against replacing a file that exists
+            let current = fs::metadata(&path).ok().map(|metadata| Validators::from_metadata(&metadata));
+            conditional::evaluate(&request, current.as_ref())?;
+
+            // Written next to the file and renamed over it, so readers get the
+            // old content or the new one but never a partial write
+            let file_name = path.file_name().ok_or(StatusCode::Conflict)?.to_string_lossy();
+            let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+            let written = fs::write(&temp_path, request.body()).and_then(|_| fs::rename(&temp_path, &path));
+
+            match written {
+                Ok(_) => {
+                    let status = if current.is_some() { StatusCode::NoContent } else { StatusCode::Created };
+                    let mut response = Response::new(status);
+                    if status == StatusCode::Created {
+                        response.set_header("Location", request.path());
+                    }
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        response.set_header("ETag", &Validators::from_metadata(&metadata).etag);
+                    }
+                    Ok(response)
+                }
+                Err(e) => {
+                    let _ = fs::remove_file(&temp_path);
+                    if e.kind() == std::io::ErrorKind::NotFound {
+                        // The directory the file would go in does not exist
+                        return Err(StatusCode::Conflict);
+                    }
+                    error!("Failed to write file: {}", e);
+                    Err(StatusCode::InternalServerError)
+                }
+            }
+        } else if !matches!(request.method(), Method::GET | Method::HEAD) {
+            // Listed for the route, but nothing here knows how to apply it
+            Err(StatusCode::NotImplemented)
         } else {
             // Handle GET requests - serve static files
             let base_path = match &self.root {
@@ -218,18 +367,16 @@ impl Route {
                 None => return Err(StatusCode::InternalServerError),
             };
 
-            // Get the relative path by removing the route path prefix
-            let relative_path = request
-                .path()
-                .strip_prefix(&self.path)
-                .unwrap_or(request.path());
-
-            // Construct full path by joining base_path with the relative path
-            let path = PathBuf::from(base_path).join(relative_path.trim_start_matches('/'));
+            let path = self.file_path(base_path, &request)?;
 
-            // Check if path exists
+            // Check if path exists, single page apps get their index instead
             if !path.exists() {
-                return Err(StatusCode::NotFound);
+                return match &self.index {
+                    Some(index) if self.spa_fallback => {
+                        FileServer::serve_file(PathBuf::from(base_path).join(index), &request)
+                    }
+                    _ => Err(StatusCode::NotFound),
+                };
             }
 
             // Handle directory
@@ -238,20 +385,20 @@ impl Route {
                 if let Some(index) = &self.index {
                     let index_path = path.join(index);
                     if index_path.exists() {
-                        return Ok(FileServer::serve_file(index_path));
+                        return FileServer::serve_file(index_path, &request);
                     }
                 }
 
                 // Show directory listing if enabled
                 if self.directory_listing {
-                    return Ok(FileServer::serve_directory_listing(&path, request.path(), &self.config));
+                    return FileServer::serve_directory_listing(&path, &request, &self.config);
                 }
 
                 return Err(StatusCode::NotFound);
             }
 
             // Serve the file
-            Ok(FileServer::serve_file(path))
+            FileServer::serve_file(path, &request)
         }
     }
 }
@@ -272,6 +419,19 @@ impl From<(RouteConfig, Config)> for Route {
             client_max_body_size: route_config.client_max_body_size,
             config,
             sessions_required: route_config.sessions_required,
+            upload_dir: route_config.upload_dir,
+            spa_fallback: route_config.spa_fallback,
+            cors: route_config.cors,
         }
     }
 }
+
+/// Joins `path` below `base`, or `None` if it would climb out of `base` with
+/// `..` or name an absolute path.
+pub fn join_below(base: &str, path: &str) -> Option<PathBuf> {
+    let relative_path = Path::new(path.trim_start_matches('/'));
+    relative_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| PathBuf::from(base).join(relative_path))
+}
@@ -1,313 +1,816 @@
 use crate::{
-    config::{Config, ErrorPages, ServerConfig},
-    error,
-    http::SessionStore,
+    config::{Config, ListenConfig, ServerConfig, TcpOptions},
+    debug, error,
+    http::{Request, RequestError, RequestLimits, Response, StatusCode},
     info,
-    server::{Listener, Mux, MAX_EVENTS},
+    server::{
+        normalize_host, signals, ConnectionLimit, Event, FdTable, Interest, KeepAlive, Listener,
+        Owner, Poller, SystemPoller, Timeouts, VirtualHost, MAX_EVENTS,
+    },
     warn,
 };
 
 use std::os::fd::RawFd;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, io};
 
-#[cfg(target_os = "linux")]
-use libc::{
-    epoll_create1, epoll_ctl, epoll_event, epoll_wait, EPOLLET, EPOLLIN, EPOLLOUT, EPOLL_CTL_ADD,
-};
-
-#[cfg(target_os = "macos")]
-use libc::{kevent, kqueue, EVFILT_READ, EV_ADD, EV_ENABLE};
+/// Longest the event loop sleeps, so shutdown and reload requests are noticed
+const MAX_WAIT: Duration = Duration::from_secs(1);
 
+/// Everything served from one listening address.
+///
+/// Every server block listing the address is a virtual host of its `Server`,
+/// and each request is routed by its `Host` header among those only. A block
+/// listing several addresses is a virtual host of each of their servers.
 pub struct Server {
     pub listeners: HashMap<i32, Box<dyn Listener>>,
-    pub server_name: Vec<String>,
-    pub host: String,
-    pub ports: Vec<u16>,
-    pub is_default: bool,
-    pub mux: Mux,
-    pub client_max_body_size: Option<String>,
-    pub error_pages: ErrorPages,
-    pub session_store: Option<SessionStore>,
+    /// Address every worker of this server listens on
+    pub listen: ListenConfig,
+    pub vhosts: Vec<VirtualHost>,
+    pub keep_alive: KeepAlive,
+    pub timeouts: Timeouts,
+    pub request_limits: RequestLimits,
+    pub tcp_options: TcpOptions,
+    /// Connections this server's worker keeps open at once
+    pub worker_connections: usize,
+    /// Connections open across all workers, shared by every copy of this server
+    pub connection_limit: ConnectionLimit,
+    /// How long active connections may take to finish on shutdown
+    pub shutdown_timeout: Duration,
+    /// Replacement configuration sent on reload, see `reload_channel`
+    reload: Option<Receiver<Server>>,
 }
 
 impl Server {
-    pub fn new(server_config: ServerConfig, config: Config) -> Server {
-        // Clone server_config before using it to avoid partial move issues
-        let server_config_clone = server_config.clone();
-
-        // Initialize session store if sessions are enabled
-        let session_store = if server_config.sessions.enabled {
-            Some(SessionStore::new(server_config.sessions.timeout_minutes))
-        } else if config.global.sessions.enabled {
-            Some(SessionStore::new(config.global.sessions.timeout_minutes))
-        } else {
-            None
+    /// Creates the server for `listen` with its first virtual host, built from
+    /// `server_config`.
+    pub fn new(
+        server_config: &ServerConfig,
+        listen: ListenConfig,
+        vhost: VirtualHost,
+        config: &Config,
+    ) -> Server {
+        // Connection settings come from the first server block on the address
+        let keep_alive = KeepAlive {
+            timeout: Duration::from_secs(server_config.timeouts.keep_alive),
+            max_requests: server_config.keep_alive_requests,
         };
+        let timeouts = Timeouts {
+            read: Duration::from_secs(server_config.timeouts.read),
+            write: Duration::from_secs(server_config.timeouts.write),
+        };
+
+        let tcp_options = server_config.tcp_options;
+        let connection_limit = ConnectionLimit::new(server_config.max_connections);
+        let worker_connections = config.global.worker_connections;
+        let shutdown_timeout = Duration::from_secs(config.global.shutdown_timeout);
 
         Server {
             listeners: HashMap::new(),
-            server_name: server_config.server_name,
-            host: server_config.host,
-            ports: server_config.ports,
-            is_default: server_config.is_default,
-            mux: Mux::new(server_config_clone, config),
-            client_max_body_size: server_config.client_max_body_size,
-            error_pages: server_config.error_pages,
-            session_store,
+            listen,
+            request_limits: RequestLimits {
+                max_body_size: vhost.max_body_size,
+                max_request_line: server_config.request_limits.max_request_line,
+                max_header_size: server_config.request_limits.max_header_size,
+                max_headers: server_config.request_limits.max_headers,
+            },
+            vhosts: vec![vhost],
+            keep_alive,
+            timeouts,
+            tcp_options,
+            worker_connections,
+            connection_limit,
+            shutdown_timeout,
+            reload: None,
         }
     }
 
-    pub fn add_listener<T: Listener + 'static + Send + Sync>(
-        &mut self,
-        listener: T,
-    ) -> io::Result<()> {
+    /// Returns a copy of this server for another worker thread. The copy has no
+    /// listeners yet and shares the session stores of this one.
+    pub fn worker(&self) -> Server {
+        Server {
+            listeners: HashMap::new(),
+            listen: self.listen.clone(),
+            vhosts: self.vhosts.clone(),
+            keep_alive: self.keep_alive,
+            timeouts: self.timeouts,
+            request_limits: self.request_limits,
+            tcp_options: self.tcp_options,
+            worker_connections: self.worker_connections,
+            connection_limit: self.connection_limit.clone(),
+            shutdown_timeout: self.shutdown_timeout,
+            reload: None,
+        }
+    }
+
+    /// Returns the sender through which a reloaded configuration reaches this
+    /// server. The listening sockets are kept; only what is built from the
+    /// config (virtual hosts, routes, limits) is swapped.
+    pub fn reload_channel(&mut self) -> Sender<Server> {
+        let (sender, receiver) = mpsc::channel();
+        self.reload = Some(receiver);
+        sender
+    }
+
+    /// Swaps in the virtual hosts and settings of a reloaded configuration.
+    /// Sessions of a virtual host that keeps its server names carry over.
+    fn apply_reload(&mut self, mut reloaded: Server, listeners: &mut [Box<dyn Listener>]) {
+        for vhost in reloaded.vhosts.iter_mut() {
+            if let Some(old) = self
+                .vhosts
+                .iter()
+                .find(|old| old.server_name == vhost.server_name)
+            {
+                if old.session_store.is_some() && vhost.session_store.is_some() {
+                    vhost.session_store = old.session_store.clone();
+                }
+            }
+        }
+
+        self.vhosts = reloaded.vhosts;
+        self.keep_alive = reloaded.keep_alive;
+        self.timeouts = reloaded.timeouts;
+        self.request_limits = reloaded.request_limits;
+        self.tcp_options = reloaded.tcp_options;
+        self.worker_connections = reloaded.worker_connections;
+        // The count of open connections carries over, only the limit changes
+        self.connection_limit
+            .set_max(reloaded.connection_limit.max());
+        self.shutdown_timeout = reloaded.shutdown_timeout;
+
+        for listener in listeners.iter_mut() {
+            listener.set_keep_alive(self.keep_alive);
+            listener.set_tcp_options(self.tcp_options);
+            listener.set_timeouts(self.timeouts);
+            listener.set_request_limits(self.request_limits);
+        }
+        info!("Configuration reloaded for {}", self.address());
+    }
+
+    pub fn listens_on(&self, address: &str) -> bool {
+        self.listen.address == address
+    }
+
+    /// The configured listen address, for logging.
+    pub fn address(&self) -> &str {
+        &self.listen.address
+    }
+
+    /// Adds the virtual host of another server block on the same address.
+    pub fn add_vhost(&mut self, vhost: VirtualHost) {
+        // Reading stops at the largest body any virtual host accepts
+        self.request_limits.max_body_size = self
+            .request_limits
+            .max_body_size
+            .zip(vhost.max_body_size)
+            .map(|(current, added)| current.max(added));
+
+        self.vhosts.push(vhost);
+    }
+
+    pub fn add_listener(&mut self, mut listener: Box<dyn Listener>) -> io::Result<()> {
+        listener.set_keep_alive(self.keep_alive);
+        listener.set_tcp_options(self.tcp_options);
+        listener.set_timeouts(self.timeouts);
+        listener.set_request_limits(self.request_limits);
         let id = listener.get_id();
-        self.listeners.insert(id, Box::new(listener));
+        self.listeners.insert(id, listener);
         Ok(())
     }
 
+    /// The virtual host answering requests no server name matches: the one marked
+    /// `is_default`, or else the first one configured.
+    fn default_vhost(&self) -> usize {
+        self.vhosts
+            .iter()
+            .position(|vhost| vhost.is_default)
+            .unwrap_or(0)
+    }
+
+    /// Picks the virtual host for a request from its `Host` header. Exact names win
+    /// over leading wildcards, which win over trailing wildcards.
+    fn vhost_for(&self, req: &Request) -> usize {
+        let host = match req.headers().get("Host") {
+            Some(host) => normalize_host(host),
+            None => return self.default_vhost(),
+        };
+
+        self.vhosts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, vhost)| vhost.match_host(&host).map(|rank| (rank, index)))
+            // On equal matches the first server block wins
+            .max_by(|(a, a_index), (b, b_index)| a.cmp(b).then(b_index.cmp(a_index)))
+            .map(|(_, index)| index)
+            .unwrap_or_else(|| self.default_vhost())
+    }
+
+    fn respond(&mut self, req: Request) -> Response {
+        let index = self.vhost_for(&req);
+        self.vhosts[index].respond(req)
+    }
+
+    /// Answers a request that cannot be served with an error page from the
+    /// default virtual host, then closes the connection.
+    fn reject(
+        &self,
+        listener: &mut Box<dyn Listener>,
+        fd: RawFd,
+        poller: &dyn Poller,
+        status: StatusCode,
+    ) {
+        let default = self.default_vhost();
+        let mut res = self.vhosts[default].mux.handle_error(status);
+        res.set_header("Connection", "close");
+
+        if listener.send_response(res, fd, poller).is_err() {
+            let _ = listener.remove_connection(fd, poller);
+        } else {
+            let _ = listener.close_connection(fd, poller);
+        }
+    }
+
+    /// How long the event loop may sleep: until the next connection deadline or
+    /// the end of the drain, and never longer than `MAX_WAIT`.
+    fn wait_timeout(listeners: &[Box<dyn Listener>], drain_deadline: Option<Instant>) -> Duration {
+        let now = Instant::now();
+        listeners
+            .iter()
+            .filter_map(|listener| listener.next_deadline())
+            .chain(drain_deadline)
+            .min()
+            .map_or(MAX_WAIT, |deadline| {
+                deadline.saturating_duration_since(now).min(MAX_WAIT)
+            })
+    }
+
     pub fn listen_and_serve(&mut self) -> io::Result<()> {
+        let mut poller = SystemPoller::new()?;
+        self.serve(&mut poller)
+    }
+
+    /// Runs the event loop on `poller` until the server is shut down.
+    pub fn serve(&mut self, poller: &mut dyn Poller) -> io::Result<()> {
         // Take ownership of the listeners
         let listeners = std::mem::take(&mut self.listeners);
         let mut listeners: Vec<Box<dyn Listener>> = listeners.into_values().collect();
 
-        // Get actual ports from listeners
-        let actual_ports: Vec<u16> = listeners.iter().map(|l| l.get_port()).collect();
-
-        // Format ports as a comma-separated list
-        let ports_str = actual_ports
+        // Addresses actually bound, which differ from the configured ones with port_scan
+        let addresses = listeners
             .iter()
-            .map(|p| p.to_string())
+            .map(|listener| listener.local_addr())
             .collect::<Vec<String>>()
             .join(", ");
 
         info!(
-            "Serving: [{}] at {}:[{}]",
-            self.server_name.join("/"),
-            self.host,
-            ports_str
+            "Serving: [{}] at [{}]",
+            self.vhosts
+                .iter()
+                .flat_map(|vhost| vhost.server_name.iter().cloned())
+                .collect::<Vec<String>>()
+                .join("/"),
+            addresses
         );
 
-        #[cfg(target_os = "linux")]
-        let global_fd = unsafe { epoll_create1(0) };
-        #[cfg(target_os = "macos")]
-        let global_fd = unsafe { kqueue() };
-
-        if global_fd < 0 {
-            return Err(io::Error::last_os_error());
+        // Register all listeners to the poller
+        let mut table = FdTable::new();
+        for (index, listener) in listeners.iter().enumerate() {
+            poller.register(listener.get_id(), Interest::Read)?;
+            table.insert(listener.get_id(), Owner::Listener(index));
         }
 
-        // Register all listeners to the global instance
-        for listener in &listeners {
-            #[cfg(target_os = "linux")]
-            {
-                let mut event = epoll_event {
-                    events: (EPOLLIN | EPOLLET) as u32,
-                    u64: listener.get_id() as u64,
-                };
+        // Event loop (single thread, handles all listeners)
+        let mut events: Vec<Event> = Vec::with_capacity(MAX_EVENTS);
+        let mut drain_deadline: Option<Instant> = None;
+        // Connections of this worker last counted in `connection_limit`
+        let mut counted = 0;
+
+        loop {
+            let timeout = Self::wait_timeout(&listeners, drain_deadline);
 
-                if unsafe { epoll_ctl(global_fd, EPOLL_CTL_ADD, listener.get_id(), &mut event) } < 0
-                {
-                    return Err(io::Error::last_os_error());
+            if let Err(err) = poller.wait(&mut events, Some(timeout)) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
                 }
+                return Err(err);
+            }
+            let poller: &dyn Poller = poller;
+
+            if drain_deadline.is_none() && signals::shutdown_requested() {
+                info!(
+                    "Shutting down {}, no longer accepting connections",
+                    self.address()
+                );
+                for listener in listeners.iter_mut() {
+                    if let Err(e) = listener.begin_drain(poller) {
+                        warn!("Failed to drain listener: {}", e);
+                    }
+                }
+                drain_deadline = Some(Instant::now() + self.shutdown_timeout);
             }
 
-            #[cfg(target_os = "macos")]
-            {
-                let changes = kevent {
-                    ident: listener.get_id() as usize,
-                    filter: EVFILT_READ as i16,
-                    flags: EV_ADD | EV_ENABLE,
-                    fflags: 0,
-                    data: 0,
-                    udata: std::ptr::null_mut(),
-                };
-
-                if unsafe {
-                    kevent(
-                        global_fd,
-                        &changes,
-                        1,
-                        std::ptr::null_mut(),
-                        0,
-                        std::ptr::null(),
-                    )
-                } < 0
-                {
-                    return Err(io::Error::last_os_error());
+            if let Some(deadline) = drain_deadline {
+                let open: usize = listeners.iter().map(|l| l.connection_count()).sum();
+                if open == 0 || Instant::now() >= deadline {
+                    if open > 0 {
+                        warn!("Shutdown timeout reached, closing {} connections", open);
+                    }
+                    // Dropping the listeners closes every remaining socket
+                    drop(listeners);
+                    self.connection_limit.update(counted, 0);
+                    info!("Server {} stopped", self.address());
+                    return Ok(());
                 }
             }
-        }
 
-        // Event loop (single thread, handles all listeners)
-        #[cfg(target_os = "linux")]
-        let mut events = vec![epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
-        #[cfg(target_os = "macos")]
-        let mut events = vec![
-            kevent {
-                ident: 0,
-                filter: 0,
-                flags: 0,
-                fflags: 0,
-                data: 0,
-                udata: std::ptr::null_mut(),
-            };
-            MAX_EVENTS
-        ];
+            if let Some(reloaded) = self.reload.as_ref().and_then(|r| r.try_recv().ok()) {
+                self.apply_reload(reloaded, &mut listeners);
+            }
 
-        loop {
-            #[cfg(target_os = "linux")]
-            let nfds = unsafe { epoll_wait(global_fd, events.as_mut_ptr(), MAX_EVENTS as i32, -1) };
-
-            #[cfg(target_os = "macos")]
-            let nfds = unsafe {
-                kevent(
-                    global_fd,
-                    std::ptr::null(),
-                    0,
-                    events.as_mut_ptr(),
-                    MAX_EVENTS as i32,
-                    std::ptr::null(),
-                )
-            };
-
-            if nfds < 0 {
-                return Err(io::Error::last_os_error());
+            // Close connections whose deadline has passed
+            for listener in listeners.iter_mut() {
+                match listener.expire_connections(poller) {
+                    Ok(timed_out) => {
+                        for fd in timed_out {
+                            debug!("Request timed out on fd={}", fd);
+                            self.reject(listener, fd, poller, StatusCode::RequestTimeout);
+                        }
+                    }
+                    Err(e) => warn!("Failed to close timed out connections: {}", e),
+                }
             }
 
-            for n in 0..nfds {
-                #[cfg(target_os = "linux")]
-                let (fd, events) = {
-                    let event = &events[n as usize];
-                    (event.u64 as RawFd, event.events)
-                };
+            // Listeners may add events for their connections while accepting
+            let mut index = 0;
+            while index < events.len() {
+                let event = events[index];
+                index += 1;
 
-                #[cfg(target_os = "macos")]
-                let (fd, event_filter, event_data) = {
-                    let event = &events[n as usize];
-                    (event.ident as RawFd, event.filter, event.data)
-                };
+                match table.get(event.fd) {
+                    Some(Owner::Listener(owner)) => {
+                        if !event.readable {
+                            continue;
+                        }
 
-                // First check if this is a listener socket
-                if let Some(listener) = listeners.iter_mut().find(|l| l.get_id() == fd) {
-                    #[cfg(target_os = "linux")]
-                    let has_read_event = events & EPOLLIN as u32 != 0;
-                    #[cfg(target_os = "macos")]
-                    let has_read_event = event_filter == EVFILT_READ as i16;
-
-                    if has_read_event {
-                        match listener.accept_connection(global_fd) {
-                            Ok(_) => (),
-                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                // No more connections to accept
-                                continue;
+                        let open: usize = listeners.iter().map(|l| l.connection_count()).sum();
+                        let available = self
+                            .worker_connections
+                            .saturating_sub(open)
+                            .min(self.connection_limit.available());
+                        let listener = &mut listeners[owner];
+
+                        match listener.accept_connection(poller, available) {
+                            Ok(accepted) => {
+                                for fd in accepted {
+                                    table.insert(fd, Owner::Connection(owner));
+                                }
                             }
                             Err(e) => error!("Accept error: {}", e),
                         }
+                        events.extend(listener.take_ready());
+                        counted = self.count_connections(&listeners, counted);
                     }
-                } else {
-                    // This is a connected socket
-                    let mut handled: bool = false;
-                    for listener in listeners.iter_mut() {
-                        #[cfg(target_os = "linux")]
-                        let has_read_event = events & EPOLLIN as u32 != 0;
-                        #[cfg(target_os = "macos")]
-                        let has_read_event = event_filter == EVFILT_READ as i16 && event_data > 0;
-
-                        if has_read_event {
-                            match listener.handle_connection(fd) {
-                                Ok(req) => {
-                                    // info!(
-                                    //                                         "Parsed HTTP Request:
-                                    // {:#?}",
-                                    //                                         req
-                                    //                                     );
-                                    //let res = self.mux.handle(req);
-                                    let res = if let Some(session_store) = &mut self.session_store {
-                                        if rand::random::<f32>() < 0.01 {
-                                            session_store.cleanup_expired();
-                                        }
-
-                                        // First, get the session ID from the request
-                                        let session_id = req
-                                            .headers()
-                                            .get_cookie("session_id")
-                                            .map(|c| c.value.clone());
-
-                                        // First, handle the session and extract just the session ID string
-                                        let session_id_for_cookie = {
-                                            // Get or create the session
-                                            let session = if let Some(id) = &session_id {
-                                                // Try to get existing session
-                                                if let Some(existing_session) =
-                                                    session_store.get_session(id)
-                                                {
-                                                    existing_session
-                                                } else {
-                                                    // Create new session if not found
-                                                    session_store.create_session()
-                                                }
-                                            } else {
-                                                // No session ID provided, create new session
-                                                session_store.create_session()
-                                            };
-                                            // Extract just the session ID as a string
-                                            session.id.clone()
-                                        }; // End of mutable borrow scope
-
-                                        let mut resp = self.mux.handle(req);
-
-                                        let cookie = session_store
-                                            .create_session_cookie(&session_id_for_cookie);
-                                        resp.add_cookie(cookie);
-
-                                        resp
-                                    } else {
-                                        self.mux.handle(req)
-                                    };
-
-                                    match listener.send_bytes(res.to_bytes(), fd) {
-                                        Ok(_) => {
-                                            handled = true;
-                                            let _ = listener.remove_connection(fd, global_fd);
-                                            break;
-                                        }
-                                        Err(e) => {
-                                            error!("Failed to send response: {}", e);
-                                            handled = true;
-                                            let _ = listener.remove_connection(fd, global_fd);
-                                            break;
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Failed to handle connection: {}", e);
-                                    match e.kind() {
-                                        io::ErrorKind::WouldBlock => {
-                                            // Not enough data yet, keep connection open
-                                            handled = true;
-                                            break;
-                                        }
-                                        _ => {
-                                            warn!("Connection error: {}", e);
-                                            handled = true;
-                                            let _ = listener.remove_connection(fd, global_fd);
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
+                    Some(Owner::Connection(owner)) => {
+                        let draining = drain_deadline.is_some();
+                        let listener = &mut listeners[owner];
+                        if !self.handle_event(listener, event, poller, draining) {
+                            // Closed by its listener without the table being told
+                            table.remove(event.fd);
+                            let _ = poller.deregister(event.fd);
                         }
                     }
+                    None => {
+                        // Not a connection of any listener, stop watching it
+                        let _ = poller.deregister(event.fd);
+                    }
+                }
+            }
+            counted = self.count_connections(&listeners, counted);
+        }
+    }
 
-                    // If no listener handled this fd, remove it from all listeners
-                    if !handled {
-                        for listener in listeners.iter_mut() {
-                            let _ = listener.remove_connection(fd, global_fd);
-                        }
+    /// Reports this worker's open connections to `connection_limit`, given the
+    /// number reported last, and returns the new number.
+    fn count_connections(&self, listeners: &[Box<dyn Listener>], counted: usize) -> usize {
+        let open = listeners.iter().map(|l| l.connection_count()).sum();
+        self.connection_limit.update(counted, open);
+        open
+    }
+
+    /// Handles readiness of a connection owned by `listener`: finishes queued
+    /// writes, then answers every request that arrived. Returns false if the
+    /// listener no longer knows the connection.
+    fn handle_event(
+        &mut self,
+        listener: &mut Box<dyn Listener>,
+        event: Event,
+        poller: &dyn Poller,
+        draining: bool,
+    ) -> bool {
+        let fd = event.fd;
+
+        // Finish writing queued responses first
+        if event.writable {
+            match listener.handle_writable(fd, poller) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return false,
+                Err(e) => {
+                    warn!("Write error on fd={}: {}", fd, e);
+                    let _ = listener.remove_connection(fd, poller);
+                    return true;
+                }
+            }
+        }
+
+        if !event.readable {
+            return true;
+        }
+
+        match listener.handle_connection(fd) {
+            Ok(requests) => {
+                let mut close = false;
+                let mut failed = false;
+
+                // Pipelined requests are answered in the order they arrived
+                for req in requests {
+                    // Nothing is kept open while draining
+                    let keep_alive = req.is_keep_alive() && !draining;
+                    let mut res = self.respond(req);
+
+                    if keep_alive {
+                        res.set_header("Connection", "keep-alive");
+                        res.set_header(
+                            "Keep-Alive",
+                            &format!("timeout={}", self.keep_alive.timeout.as_secs()),
+                        );
+                    } else {
+                        res.set_header("Connection", "close");
+                    }
+
+                    if let Err(e) = listener.send_response(res, fd, poller) {
+                        error!("Failed to send response: {}", e);
+                        failed = true;
+                        break;
+                    }
+
+                    if !keep_alive {
+                        close = true;
+                        break;
+                    }
+                }
+
+                if failed {
+                    let _ = listener.remove_connection(fd, poller);
+                } else if close {
+                    // Closed once the queued responses are written
+                    let _ = listener.close_connection(fd, poller);
+                }
+            }
+            Err(e) => {
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => {
+                        // Not enough data yet, keep connection open
+                    }
+                    io::ErrorKind::NotFound => return false,
+                    io::ErrorKind::InvalidData => {
+                        // Malformed or oversized request: answer it, then close
+                        warn!("Rejecting request on fd={}: {}", fd, e);
+                        let status = RequestError::status_of(&e).unwrap_or(StatusCode::BadRequest);
+                        self.reject(listener, fd, poller, status);
+                    }
+                    io::ErrorKind::ConnectionAborted => {
+                        let _ = listener.remove_connection(fd, poller);
+                    }
+                    _ => {
+                        warn!("Connection error: {}", e);
+                        let _ = listener.remove_connection(fd, poller);
                     }
                 }
             }
         }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServeDir;
+    use crate::http::{methods::Method, Headers};
+    use crate::server::{bind_socket, MockPoller, PollListener, ReusePort};
+
+    use std::cell::RefCell;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    /// A directory of files served by the test, removed when dropped.
+    struct TestRoot(PathBuf);
+
+    impl TestRoot {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let root = std::env::temp_dir().join(format!("kang-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&root).unwrap();
+            for (file, content) in files {
+                fs::write(root.join(file), content).unwrap();
+            }
+            TestRoot(root)
+        }
+    }
+
+    impl Drop for TestRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A `kang serve-dir` server for `root`, listening on a free local port with
+    /// `tcp_options`. Returns the server, the listening descriptor and the bound address.
+    fn serve_dir(root: &TestRoot, tcp_options: TcpOptions) -> (Server, RawFd, String) {
+        let options = ServeDir {
+            root: root.0.to_string_lossy().into_owned(),
+            ..ServeDir::default()
+        };
+        let mut server = options.config().unwrap().create_servers().remove(0);
+        let listen = ListenConfig::new("127.0.0.1:0");
+        let socket = bind_socket(&listen, ReusePort::No, &tcp_options).unwrap();
+        let listener = PollListener::from_socket(socket).unwrap();
+        let (fd, addr) = (listener.id, listener.addr.clone());
+        server.add_listener(Box::new(listener)).unwrap();
+        (server, fd, addr)
+    }
+
+    fn readable(fd: RawFd) -> Event {
+        Event {
+            fd,
+            readable: true,
+            writable: false,
+        }
+    }
+
+    fn writable(fd: RawFd) -> Event {
+        Event {
+            fd,
+            readable: false,
+            writable: true,
+        }
+    }
+
+    /// The only connection registered besides the listener.
+    fn connection(poller: &MockPoller, listener: RawFd) -> RawFd {
+        let connections = poller.registered_except(listener);
+        assert_eq!(connections.len(), 1, "expected a single connection");
+        connections[0]
+    }
+
+    fn end_of_script(result: io::Result<()>) {
+        let err = result.unwrap_err();
+        assert_eq!(err.to_string(), "Mock poller script exhausted");
+    }
+
+    #[test]
+    fn serves_a_request_and_closes_the_connection() {
+        let root = TestRoot::new("serve-close", &[("index.html", b"hello")]);
+        let (mut server, listener, addr) = serve_dir(&root, TcpOptions::default());
+
+        // Sent before the event loop starts, waiting in the kernel until it is read
+        let mut client = TcpStream::connect(&addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let response = Rc::new(RefCell::new(Vec::new()));
+        let received = Rc::clone(&response);
+
+        let mut poller = MockPoller::new();
+        poller.then(move |poller| {
+            assert_eq!(poller.interest(listener), Some(Interest::Read));
+            vec![readable(listener)]
+        });
+        poller.then(move |poller| vec![readable(connection(poller, listener))]);
+        poller.then(move |poller| {
+            // Written in full right away, then closed
+            assert!(poller.registered_except(listener).is_empty());
+            client.read_to_end(&mut received.borrow_mut()).unwrap();
+            Vec::new()
+        });
+
+        end_of_script(server.serve(&mut poller));
+
+        let response = String::from_utf8(response.take()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn finishes_a_large_response_once_writable() {
+        let body: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let root = TestRoot::new("serve-writable", &[("large.bin", &body)]);
+        // A small send buffer, inherited by connections, makes the first write stop short of the end
+        let tcp_options = TcpOptions {
+            so_sndbuf: Some(4096),
+            ..TcpOptions::default()
+        };
+        let (mut server, listener, addr) = serve_dir(&root, tcp_options);
+
+        let client = Rc::new(RefCell::new(TcpStream::connect(&addr).unwrap()));
+        client
+            .borrow_mut()
+            .write_all(b"GET /large.bin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+
+        let response = Rc::new(RefCell::new(Vec::new()));
+        let writable_events = Rc::new(RefCell::new(0));
+
+        let mut poller = MockPoller::new();
+        poller.then(move |_| vec![readable(listener)]);
+        poller.then(move |poller| vec![readable(connection(poller, listener))]);
+        {
+            let (client, response, writable_events) = (
+                Rc::clone(&client),
+                Rc::clone(&response),
+                Rc::clone(&writable_events),
+            );
+            poller.repeat(move |poller| {
+                let fd = connection(poller, listener);
+                if poller.interest(fd) != Some(Interest::ReadWrite) {
+                    return None;
+                }
+
+                // Make room in the socket buffers, then report it
+                let mut client = client.borrow_mut();
+                client.set_nonblocking(true).unwrap();
+                let mut chunk = [0; 64 * 1024];
+                loop {
+                    match client.read(&mut chunk) {
+                        Ok(0) => panic!("connection closed mid-response"),
+                        Ok(n) => response.borrow_mut().extend_from_slice(&chunk[..n]),
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => panic!("read failed: {}", e),
+                    }
+                }
+                *writable_events.borrow_mut() += 1;
+                Some(vec![writable(fd)])
+            });
+        }
+        {
+            let (client, response) = (Rc::clone(&client), Rc::clone(&response));
+            poller.then(move |poller| {
+                // Kept open for the next request, now that everything is queued
+                let fd = connection(poller, listener);
+                assert_eq!(poller.interest(fd), Some(Interest::Read));
+
+                let mut client = client.borrow_mut();
+                client.set_nonblocking(false).unwrap();
+                let mut response = response.borrow_mut();
+                let head_end = loop {
+                    if let Some(pos) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                    let mut chunk = [0; 4096];
+                    let n = client.read(&mut chunk).unwrap();
+                    response.extend_from_slice(&chunk[..n]);
+                };
+                let mut rest = vec![0; head_end + 1024 * 1024 - response.len()];
+                client.read_exact(&mut rest).unwrap();
+                response.extend_from_slice(&rest);
+
+                // The client leaves
+                client.shutdown(Shutdown::Write).unwrap();
+                vec![readable(fd)]
+            });
+        }
+        poller.then(move |poller| {
+            assert!(poller.registered_except(listener).is_empty());
+            Vec::new()
+        });
+
+        end_of_script(server.serve(&mut poller));
+
+        assert!(
+            *writable_events.borrow() > 0,
+            "the response was written in one go"
+        );
+        let response = response.take();
+        let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&response[..head_end]);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Connection: keep-alive\r\n"));
+        assert!(response[head_end..] == body[..]);
+    }
+
+    /// The servers of a config whose blocks have the given names and ports,
+    /// the block at `default` being marked `is_default`.
+    fn servers(blocks: &[(&[&str], &[u16])], default: Option<usize>) -> Vec<Server> {
+        let blocks: Vec<serde_json::Value> = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, (names, ports))| {
+                serde_json::json!({
+                    "server_name": names,
+                    "error_pages": {},
+                    "host": "127.0.0.1",
+                    "ports": ports,
+                    "is_default": default == Some(index),
+                    "routes": [],
+                })
+            })
+            .collect();
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "global": { "cgi": {} },
+            "servers": blocks,
+        }))
+        .unwrap();
+        config.create_servers()
+    }
+
+    /// The first server name of the virtual host `server` picks for `host`.
+    fn vhost_name(server: &Server, host: Option<&str>) -> String {
+        let mut request = Request::new(Method::GET, "/", "HTTP/1.1");
+        let mut headers = Headers::new();
+        if let Some(host) = host {
+            headers.append("Host", host);
+        }
+        request.set_headers(headers);
+        server.vhosts[server.vhost_for(&request)].server_name[0].clone()
+    }
+
+    #[test]
+    fn picks_the_vhost_by_host_header() {
+        let servers = servers(
+            &[
+                (&["example.com", "www.example.com"], &[8080]),
+                (&["*.example.com"], &[8080]),
+                (&["www.example.*"], &[8080]),
+                (&["api.example.com"], &[8080]),
+            ],
+            None,
+        );
+        assert_eq!(servers.len(), 1);
+        let server = &servers[0];
+
+        assert_eq!(
+            vhost_name(server, Some("api.example.com")),
+            "api.example.com"
+        );
+        assert_eq!(vhost_name(server, Some("www.example.com")), "example.com");
+        // Leading wildcards win over trailing ones
+        assert_eq!(
+            vhost_name(server, Some("blog.example.com")),
+            "*.example.com"
+        );
+        assert_eq!(vhost_name(server, Some("www.example.org")), "www.example.*");
+    }
+
+    #[test]
+    fn normalizes_the_host_header() {
+        let servers = servers(
+            &[(&["default.test"], &[8080]), (&["Example.com"], &[8080])],
+            None,
+        );
+        let server = &servers[0];
+
+        assert_eq!(vhost_name(server, Some("example.com:8080")), "Example.com");
+        assert_eq!(vhost_name(server, Some("EXAMPLE.COM")), "Example.com");
+        assert_eq!(vhost_name(server, Some("Example.Com.:80")), "Example.com");
+    }
+
+    #[test]
+    fn falls_back_to_the_default_vhost() {
+        let blocks: &[(&[&str], &[u16])] =
+            &[(&["first.test"], &[8080]), (&["second.test"], &[8080])];
+
+        let first = &servers(blocks, None)[0];
+        assert_eq!(vhost_name(first, Some("unknown.test")), "first.test");
+        assert_eq!(vhost_name(first, None), "first.test");
+
+        let marked = &servers(blocks, Some(1))[0];
+        assert_eq!(vhost_name(marked, Some("unknown.test")), "second.test");
+        assert_eq!(vhost_name(marked, Some("first.test:8080")), "first.test");
+        assert_eq!(vhost_name(marked, None), "second.test");
+    }
+
+    #[test]
+    fn only_picks_vhosts_on_the_same_address() {
+        let servers = servers(
+            &[
+                (&["a.test"], &[8080]),
+                (&["b.test"], &[8081]),
+                (&["c.test"], &[8080, 8081]),
+            ],
+            None,
+        );
+        assert_eq!(servers.len(), 2);
+        let (a, b) = (&servers[0], &servers[1]);
+        assert!(a.listens_on("127.0.0.1:8080") && b.listens_on("127.0.0.1:8081"));
+
+        assert_eq!(vhost_name(a, Some("c.test")), "c.test");
+        assert_eq!(vhost_name(b, Some("c.test")), "c.test");
+        // Not listed on 8081, so its default answers
+        assert_eq!(vhost_name(b, Some("a.test")), "b.test");
     }
 }
@@ -0,0 +1,47 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+static UPGRADE_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    // Only async-signal-safe work here: the event loops poll these flags
+    match signal {
+        libc::SIGHUP => RELOAD_REQUESTED.store(true, Ordering::SeqCst),
+        libc::SIGUSR2 => UPGRADE_REQUESTED.store(true, Ordering::SeqCst),
+        _ => SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst),
+    }
+}
+
+/// Installs handlers so SIGTERM and SIGINT request a graceful shutdown,
+/// SIGHUP requests a configuration reload and SIGUSR2 a binary upgrade.
+pub fn install() -> io::Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGUSR2] {
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut action.sa_mask) };
+
+        if unsafe { libc::sigaction(signal, &action, ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Returns true once per SIGHUP received since the last call.
+pub fn take_reload_request() -> bool {
+    RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
+
+/// Returns true once per SIGUSR2 received since the last call.
+pub fn take_upgrade_request() -> bool {
+    UPGRADE_REQUESTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,45 @@
+use std::os::unix::io::RawFd;
+
+/// What a file descriptor reported by the poller belongs to. The index is the
+/// position of the listener in the event loop's listener list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    /// The listening socket of a listener, or its ring for io_uring
+    Listener(usize),
+    /// A connection accepted by a listener
+    Connection(usize),
+}
+
+/// Maps every file descriptor the event loop watches to its owner, so each
+/// event goes straight to the listener holding the connection's state.
+///
+/// File descriptors are small integers the kernel reuses, so the table is a
+/// vector indexed by fd. An entry left behind by a connection its listener
+/// closed on its own is overwritten when the fd is accepted again; until then
+/// the owner reports the connection as unknown and the entry is removed.
+#[derive(Debug, Default)]
+pub struct FdTable {
+    owners: Vec<Option<Owner>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, fd: RawFd, owner: Owner) {
+        let index = fd as usize;
+        if index >= self.owners.len() {
+            self.owners.resize(index + 1, None);
+        }
+        self.owners[index] = Some(owner);
+    }
+
+    pub fn get(&self, fd: RawFd) -> Option<Owner> {
+        self.owners.get(fd as usize).copied().flatten()
+    }
+
+    pub fn remove(&mut self, fd: RawFd) -> Option<Owner> {
+        self.owners.get_mut(fd as usize).and_then(Option::take)
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{
+    config::{Config, ErrorPages, ServerConfig},
+    http::{Request, Response, SessionStore, SharedSessionStore},
+    server::Mux,
+    utils::parse_size,
+};
+
+/// How well a server name matched a `Host` header. Variants are declared from
+/// weakest to strongest, so the best match is the greatest value; among
+/// wildcards the longer name wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NameMatch {
+    /// `www.example.*`
+    TrailingWildcard(usize),
+    /// `*.example.com`
+    LeadingWildcard(usize),
+    Exact,
+}
+
+/// One server block sharing an address with others, picked by the `Host` header.
+///
+/// Each virtual host keeps its own routes, error pages and sessions. Clones
+/// made for other workers share the session store.
+#[derive(Clone)]
+pub struct VirtualHost {
+    pub server_name: Vec<String>,
+    pub is_default: bool,
+    pub mux: Mux,
+    pub client_max_body_size: Option<String>,
+    pub error_pages: ErrorPages,
+    pub session_store: Option<SharedSessionStore>,
+    /// Largest body any route accepts, `None` meaning unlimited
+    pub max_body_size: Option<usize>,
+}
+
+impl VirtualHost {
+    pub fn new(server_config: ServerConfig, config: Config) -> VirtualHost {
+        // Initialize session store if sessions are enabled
+        let session_store = if server_config.sessions.enabled {
+            Some(SessionStore::new(server_config.sessions.timeout_minutes))
+        } else if config.global.sessions.enabled {
+            Some(SessionStore::new(config.global.sessions.timeout_minutes))
+        } else {
+            None
+        }
+        .map(|store| Arc::new(Mutex::new(store)));
+
+        let max_body_size = Self::max_body_size(&server_config, &config);
+
+        VirtualHost {
+            max_body_size,
+            server_name: server_config.server_name.clone(),
+            is_default: server_config.is_default,
+            client_max_body_size: server_config.client_max_body_size.clone(),
+            error_pages: server_config.error_pages.clone(),
+            mux: Mux::new(server_config, config),
+            session_store,
+        }
+    }
+
+    /// Bodies are received before the route is known, so the largest limit of any
+    /// route is the cap applied while reading; the mux then enforces the limit of
+    /// the matched route.
+    fn max_body_size(server_config: &ServerConfig, config: &Config) -> Option<usize> {
+        let default = server_config
+            .client_max_body_size
+            .as_ref()
+            .or(config.global.client_max_body_size.as_ref());
+
+        default
+            .into_iter()
+            .chain(
+                server_config
+                    .routes
+                    .iter()
+                    .filter_map(|route| route.client_max_body_size.as_ref()),
+            )
+            .filter_map(|size| parse_size(size))
+            .max()
+            .map(|size| size as usize)
+    }
+
+    /// Returns the best match of `host` against this virtual host's names.
+    /// `host` must already be normalized, see `normalize_host`.
+    pub fn match_host(&self, host: &str) -> Option<NameMatch> {
+        self.server_name
+            .iter()
+            .filter_map(|name| match_name(&name.to_lowercase(), host))
+            .max()
+    }
+
+    /// Routes a request through the mux, attaching the session cookie when sessions are enabled.
+    pub fn respond(&mut self, req: Request) -> Response {
+        let mut session_store = match &self.session_store {
+            // A worker panicking mid-request leaves the sessions themselves intact
+            Some(session_store) => session_store.lock().unwrap_or_else(PoisonError::into_inner),
+            None => return self.mux.handle(req),
+        };
+
+        if rand::random::<f32>() < 0.01 {
+            session_store.cleanup_expired();
+        }
+
+        // First, get the session ID from the request
+        let session_id = req
+            .headers()
+            .get_cookie("session_id")
+            .map(|c| c.value.clone());
+
+        // First, handle the session and extract just the session ID string
+        let session_id_for_cookie = {
+            // Get or create the session
+            let session = if let Some(id) = &session_id {
+                // Try to get existing session
+                if let Some(existing_session) = session_store.get_session(id) {
+                    existing_session
+                } else {
+                    // Create new session if not found
+                    session_store.create_session()
+                }
+            } else {
+                // No session ID provided, create new session
+                session_store.create_session()
+            };
+            // Extract just the session ID as a string
+            session.id.clone()
+        }; // End of mutable borrow scope
+
+        let cookie = session_store.create_session_cookie(&session_id_for_cookie);
+        // Don't hold the lock while the request is handled
+        drop(session_store);
+        let mut resp = self.mux.handle(req);
+        resp.add_cookie(cookie);
+
+        resp
+    }
+}
+
+/// Lowercases a `Host` header value and strips its port and any trailing dot.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = if host.starts_with('[') {
+        // IPv6 literal, the port follows the closing bracket
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
+
+/// Matches one configured server name against a normalized host.
+fn match_name(name: &str, host: &str) -> Option<NameMatch> {
+    if let Some(suffix) = name.strip_prefix('*') {
+        // "*.example.com" matches any subdomain, but not "example.com" itself
+        return (suffix.starts_with('.') && host.len() > suffix.len() && host.ends_with(suffix))
+            .then_some(NameMatch::LeadingWildcard(name.len()));
+    }
+
+    if let Some(prefix) = name.strip_suffix('*') {
+        // "www.example.*" matches any top-level part after the prefix
+        return (prefix.ends_with('.') && host.len() > prefix.len() && host.starts_with(prefix))
+            .then_some(NameMatch::TrailingWildcard(name.len()));
+    }
+
+    (name == host).then_some(NameMatch::Exact)
+}
+
+/// Returns true if `name` is an exact name or a wildcard in one of the supported forms.
+pub fn is_valid_server_name(name: &str) -> bool {
+    let stars = name.matches('*').count();
+    if name.is_empty() || stars > 1 {
+        return false;
+    }
+    stars == 0
+        || (name.starts_with("*.") && name.len() > 2)
+        || (name.ends_with(".*") && name.len() > 2)
+}